@@ -1,7 +1,73 @@
 use crate::extra::ErrorStatus;
 use crate::parser;
+use chrono::{Days, Months, NaiveDate};
+use std::cmp;
 use std::fmt;
 use std::str::FromStr;
+use std::sync::OnceLock;
+
+pub static FORMAT_VERSION: u32 = 14;
+// scores are stored on a 100-point scale and shown in the scoring system of the config
+pub static MAX_RATE: u8 = 100;
+// 0 is no priority
+pub static MAX_PRIORITY: u8 = 5;
+static SCORING: OnceLock<Scoring> = OnceLock::new();
+// minutes of an episode or a chapter by media type from the config
+static DURATIONS: OnceLock<Vec<(MediaType, u16)>> = OnceLock::new();
+static FORMAT_HEADER: &str = "#! watch-list format ";
+// ids of deleted items are not given out again
+static NEXT_ID_HEADER: &str = "#! next id ";
+
+// upgrades a list line to the next format version or reports a value that cannot be upgraded
+type Migration = fn(&str) -> Result<String, ParseError>;
+
+// index 0 upgrades version 1
+static MIGRATIONS: [Migration; 13] = [
+    // 1 -> 2: items without id get one on load
+    |line| Ok(line.to_owned()),
+    // 2 -> 3: start and finish dates are optional
+    |line| Ok(line.to_owned()),
+    // 3 -> 4: tags are optional
+    |line| Ok(line.to_owned()),
+    // 4 -> 5: backslashes in quoted text are escaped
+    |line| Ok(line.replace('\\', "\\\\")),
+    // 5 -> 6: aliases are optional
+    |line| Ok(line.to_owned()),
+    // 6 -> 7: media type and volumes are optional
+    |line| Ok(line.to_owned()),
+    // 7 -> 8: rewatches are optional
+    |line| Ok(line.to_owned()),
+    // 8 -> 9: relations are optional
+    |line| Ok(line.to_owned()),
+    // 9 -> 10: 10-point scores become 100-point ones
+    |line| {
+        let mut iter = parser::Splitter::new(line, parser::SplitFormat::Anime);
+        while let Some(token) = iter.next() {
+            let is_key = token == "score" && iter.position() > 0 && !line[..iter.position()].ends_with('"');
+            if let (true, Some(value)) = (is_key, iter.next()) {
+                let (start, end) = (iter.position(), iter.position() + value.len());
+                return match scale_score(value) {
+                    Some(value) => Ok(format!("{}{}{}", &line[..start], value, &line[end..])),
+                    None => Err(ParseError::new(line, start, value, Field::OldScore)),
+                };
+            }
+        }
+        Ok(line.to_owned())
+    },
+    // 10 -> 11: airing schedules are optional
+    |line| Ok(line.to_owned()),
+    // 11 -> 12: priorities are optional
+    |line| Ok(line.to_owned()),
+    // 12 -> 13: custom fields are optional
+    |line| Ok(line.to_owned()),
+    // 13 -> 14: episode durations are optional
+    |line| Ok(line.to_owned()),
+];
+
+// 10-point score of a list before version 10, other values were never valid
+pub fn scale_score(value: &str) -> Option<String> {
+    value.parse::<u8>().ok().filter(|value| *value <= 10).map(|value| (value * 10).to_string())
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Status {
@@ -13,19 +79,91 @@ pub enum Status {
     Error,
 }
 
+// manga and novels count chapters in `progress` and have volumes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaType {
+    Tv,
+    Movie,
+    Ova,
+    Ona,
+    Special,
+    Manga,
+    Novel,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scoring {
+    Ten,
+    Hundred,
+    Stars,
+    Decimal,
+}
+
+// every relation is stored on both items, with the inverse on the other one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Relation {
+    Sequel,
+    Prequel,
+    SideStory,
+    MainStory,
+    Franchise,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SeriesCounter {
     Value(u16),
     OnGoing,
 }
 
-#[derive(Debug)]
+// schedule of an ongoing series, the first episode airs on `start` and
+// the next ones every `interval` days, `airing 2024-04-05/7/12`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Airing {
+    pub start: NaiveDate,
+    pub interval: u16,
+    // expected number of episodes
+    pub total: SeriesCounter,
+}
+
+// dates of a find or filter command, `?` matches items without a date
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateRange {
+    Between(NaiveDate, NaiveDate),
+    Missing,
+}
+
+#[derive(Debug, Clone)]
 pub struct Item {
+    // 0 until the item is added to a list
+    pub id: u32,
     pub name: String,
+    // alternative titles, one `alias` key for each
+    pub aliases: Vec<String>,
     pub status: Status,
+    pub kind: MediaType,
     pub progress: u16,
     pub maximum: SeriesCounter,
+    pub volume: u16,
+    pub volumes: SeriesCounter,
+    // minutes of an episode, the default of the media type if not set
+    pub duration: Option<u16>,
     pub rate: u8,
+    pub priority: u8,
+    // finished rewatches, progress of a rewatch is counted again from zero
+    pub rewatches: u16,
+    pub rewatching: bool,
+    pub started: Option<NaiveDate>,
+    pub finished: Option<NaiveDate>,
+    pub airing: Option<Airing>,
+    pub tags: Vec<String>,
+    // related item ids
+    pub relations: Vec<(Relation, u32)>,
+    // user defined `key=value` fields, one `custom` key for each
+    pub custom: Vec<(String, String)>,
+    pub note: String,
+    pub leading: Vec<String>,
+    pub source: Option<String>,
 }
 
 impl<'a> From<&'a str> for Status {
@@ -41,6 +179,208 @@ impl<'a> From<&'a str> for Status {
     }
 }
 
+impl<'a> From<&'a str> for MediaType {
+    fn from(s: &'a str) -> MediaType {
+        match s {
+            "tv" | "t" => MediaType::Tv,
+            "movie" | "m" => MediaType::Movie,
+            "ova" | "o" => MediaType::Ova,
+            "ona" | "n" => MediaType::Ona,
+            "special" | "s" => MediaType::Special,
+            "manga" | "g" => MediaType::Manga,
+            "novel" | "l" => MediaType::Novel,
+            _ => MediaType::Error,
+        }
+    }
+}
+
+impl MediaType {
+    pub const ALL: [MediaType; 7] = [
+        MediaType::Tv,
+        MediaType::Movie,
+        MediaType::Ova,
+        MediaType::Ona,
+        MediaType::Special,
+        MediaType::Manga,
+        MediaType::Novel,
+    ];
+
+    pub fn is_reading(self) -> bool {
+        matches!(self, MediaType::Manga | MediaType::Novel)
+    }
+
+    // set once from the config, missing types keep the built-in duration
+    pub fn init_durations(durations: Vec<(MediaType, u16)>) {
+        let _ = DURATIONS.set(durations);
+    }
+
+    pub fn duration(self) -> u16 {
+        let configured = DURATIONS.get().and_then(|durations| durations.iter().find(|(kind, _)| *kind == self));
+        match (configured, self) {
+            (Some((_, duration)), _) => *duration,
+            (None, MediaType::Movie) => 100,
+            (None, MediaType::Ova) => 30,
+            (None, MediaType::Special) => 15,
+            (None, MediaType::Manga) => 5,
+            (None, MediaType::Novel) => 20,
+            (None, _) => 24,
+        }
+    }
+}
+
+impl Airing {
+    // weekly with an unknown number of episodes
+    pub fn new(start: NaiveDate) -> Airing {
+        Airing { start, interval: 7, total: SeriesCounter::OnGoing }
+    }
+
+    // episodes out by `date`
+    pub fn aired(&self, date: NaiveDate) -> u16 {
+        let days = (date - self.start).num_days();
+        if days < 0 {
+            return 0;
+        }
+        let aired = u16::try_from(days / self.interval as i64 + 1).unwrap_or(u16::MAX);
+        match self.total {
+            SeriesCounter::Value(total) => cmp::min(aired, total),
+            SeriesCounter::OnGoing => aired,
+        }
+    }
+
+    // air date of the first episode that is not out by `date`
+    pub fn next(&self, date: NaiveDate) -> Option<NaiveDate> {
+        let aired = self.aired(date);
+        if matches!(self.total, SeriesCounter::Value(total) if aired >= total) {
+            return None;
+        }
+        self.start.checked_add_days(Days::new(aired as u64 * self.interval as u64))
+    }
+}
+
+impl Scoring {
+    pub fn from(s: &str) -> Option<Scoring> {
+        match s {
+            "10" => Some(Scoring::Ten),
+            "100" => Some(Scoring::Hundred),
+            "5" | "stars" => Some(Scoring::Stars),
+            "decimal" => Some(Scoring::Decimal),
+            _ => None,
+        }
+    }
+
+    // set once from the config before any score is parsed or shown
+    pub fn init(self) {
+        let _ = SCORING.set(self);
+    }
+
+    pub fn current() -> Scoring {
+        SCORING.get().copied().unwrap_or(Scoring::Ten)
+    }
+
+    // stored points for one point of the system
+    fn step(self) -> u8 {
+        match self {
+            Scoring::Ten => 10,
+            Scoring::Stars => 20,
+            Scoring::Hundred | Scoring::Decimal => 1,
+        }
+    }
+
+    pub fn parse(self, s: &str) -> Option<u8> {
+        match self {
+            Scoring::Decimal => {
+                let value: f32 = s.parse().ok()?;
+                (0.0..=10.0).contains(&value).then(|| (value * 10.0).round() as u8)
+            }
+            _ => {
+                let value: u8 = s.parse().ok()?;
+                (value <= MAX_RATE / self.step()).then(|| value * self.step())
+            }
+        }
+    }
+
+    // stored scores that are shown as `s`
+    pub fn range(self, s: &str) -> Option<(u8, u8)> {
+        let value = self.parse(s)?;
+        let step = self.step();
+        Some((value.saturating_sub(step / 2), cmp::min(MAX_RATE, value + (step - 1) / 2)))
+    }
+
+    pub fn format(self, rate: u8) -> String {
+        let value = (rate + self.step() / 2) / self.step();
+        match self {
+            Scoring::Ten => format!("{:>2} / 10", value),
+            Scoring::Hundred => format!("{:>3} / 100", value),
+            Scoring::Stars => format!("{}{}", "★".repeat(value as usize), "☆".repeat(5 - value as usize)),
+            Scoring::Decimal => format!("{:>4.1} / 10", rate as f32 / 10.0),
+        }
+    }
+}
+
+impl Relation {
+    pub fn from(s: &str) -> Option<Relation> {
+        match s {
+            "sequel" | "s" => Some(Relation::Sequel),
+            "prequel" | "p" => Some(Relation::Prequel),
+            "side" | "i" => Some(Relation::SideStory),
+            "main" | "m" => Some(Relation::MainStory),
+            "franchise" | "f" => Some(Relation::Franchise),
+            _ => None,
+        }
+    }
+
+    pub fn inverse(self) -> Relation {
+        match self {
+            Relation::Sequel => Relation::Prequel,
+            Relation::Prequel => Relation::Sequel,
+            Relation::SideStory => Relation::MainStory,
+            Relation::MainStory => Relation::SideStory,
+            Relation::Franchise => Relation::Franchise,
+        }
+    }
+}
+
+// `sequel:5,side:7`
+fn relations_to_string(relations: &[(Relation, u32)]) -> String {
+    let relations: Vec<_> = relations.iter().map(|(kind, id)| format!("{}:{}", kind, id)).collect();
+    relations.join(",")
+}
+
+// `key=value`, the key is trimmed and cannot be empty, the value is a single line
+pub fn parse_custom(s: &str) -> Option<(String, String)> {
+    let (key, value) = s.split_once('=')?;
+    let key = key.trim();
+    (!key.is_empty() && !value.contains('\n')).then(|| (key.to_owned(), value.to_owned()))
+}
+
+fn relations_from_str(s: &str) -> Option<Vec<(Relation, u32)>> {
+    let mut result = Vec::new();
+    for relation in s.split(',').map(str::trim).filter(|relation| !relation.is_empty()) {
+        let (kind, id) = relation.split_once(':')?;
+        let id = id.parse().ok().filter(|id| *id != 0)?;
+        result.push((Relation::from(kind)?, id));
+    }
+    Some(result)
+}
+
+impl FromStr for Airing {
+    type Err = ErrorStatus;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        let start = parts.next().unwrap_or_default().parse().map_err(|_| ErrorStatus::DateParseError)?;
+        let interval = match parts.next().map(str::parse) {
+            Some(Ok(interval)) if interval != 0 => interval,
+            _ => return Err(ErrorStatus::IntParseError),
+        };
+        let total = parts.next().unwrap_or_default().parse()?;
+        match parts.next() {
+            Some(_) => Err(ErrorStatus::IntParseError),
+            None => Ok(Airing { start, interval, total }),
+        }
+    }
+}
+
 impl FromStr for SeriesCounter {
     type Err = ErrorStatus;
 
@@ -64,28 +404,470 @@ impl SeriesCounter {
     }
 }
 
+// first and last day of `2024`, `2024-03` or `2024-03-15`
+fn period(s: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let parts: Vec<_> = s.split('-').collect();
+    match parts[..] {
+        [year] => {
+            let year = year.parse().ok()?;
+            Some((NaiveDate::from_ymd_opt(year, 1, 1)?, NaiveDate::from_ymd_opt(year, 12, 31)?))
+        }
+        [year, month] => {
+            let first = NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, 1)?;
+            Some((first, first.checked_add_months(Months::new(1))?.pred_opt()?))
+        }
+        _ => s.parse().ok().map(|date| (date, date)),
+    }
+}
+
+impl FromStr for DateRange {
+    type Err = ErrorStatus;
+
+    // a single period or `from..to` where both ends are optional
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let range = match s.split_once("..") {
+            _ if s == "?" => return Ok(DateRange::Missing),
+            Some((from, to)) => {
+                let from = if from.is_empty() { Some(NaiveDate::MIN) } else { period(from).map(|(first, _)| first) };
+                let to = if to.is_empty() { Some(NaiveDate::MAX) } else { period(to).map(|(_, last)| last) };
+                from.zip(to)
+            }
+            None => period(s),
+        };
+        range.map(|(from, to)| DateRange::Between(from, to)).ok_or(ErrorStatus::DateParseError)
+    }
+}
+
+impl DateRange {
+    pub fn contains(self, date: Option<NaiveDate>) -> bool {
+        match (self, date) {
+            (DateRange::Between(from, to), Some(date)) => from <= date && date <= to,
+            (DateRange::Missing, None) => true,
+            _ => false,
+        }
+    }
+}
+
+fn date_to_string(date: Option<NaiveDate>) -> String {
+    date.map(|date| date.to_string()).unwrap_or_default()
+}
+
+fn date_from_str(s: &str) -> Option<Option<NaiveDate>> {
+    if s.is_empty() {
+        Some(None)
+    } else {
+        s.parse().ok().map(Some)
+    }
+}
+
+// tags are separated by commas, empty ones are dropped
+pub fn parse_tags(s: &str) -> Vec<String> {
+    s.split(',').map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_owned).collect()
+}
+
+// lists without a header are version 1
+pub fn parse_header(line: &str) -> Option<u32> {
+    line.trim_end().strip_prefix(FORMAT_HEADER).and_then(|version| version.parse().ok())
+}
+
+pub fn header() -> String {
+    format!("{}{}", FORMAT_HEADER, FORMAT_VERSION)
+}
+
+pub fn parse_next_id(line: &str) -> Option<u32> {
+    line.trim_end().strip_prefix(NEXT_ID_HEADER).and_then(|id| id.parse().ok())
+}
+
+pub fn next_id_header(id: u32) -> String {
+    format!("{}{}", NEXT_ID_HEADER, id)
+}
+
+pub fn migrate(line: &str, version: u32) -> Result<String, ParseError> {
+    let first = version.saturating_sub(1) as usize;
+    MIGRATIONS.iter().skip(first).try_fold(line.to_owned(), |line, migration| migration(&line))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Name,
+    Status,
+    ProgressKey,
+    Progress,
+    ScoreKey,
+    Score,
+    // score of a list before version 10
+    OldScore,
+    Type,
+    Volumes,
+    Rewatches,
+    Id,
+    Date,
+    Tags,
+    Note,
+    Alias,
+    Relation,
+    Airing,
+    Priority,
+    Custom,
+    Duration,
+    Key,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+    pub expected: Field,
+}
+
+impl ParseError {
+    pub fn new(text: &str, position: usize, token: &str, expected: Field) -> ParseError {
+        let column = text[..position].chars().count() + 1;
+        ParseError { line: 0, column, token: token.to_owned(), expected }
+    }
+
+    pub fn at_line(self, line: usize) -> ParseError {
+        ParseError { line, ..self }
+    }
+}
+
 impl Item {
-    // TODO:
-    // - rewrite this code
-    // - fix (...).parse().unwrap()
-    pub fn new(text: &str) -> Item {
-        let raw: Vec<_> = parser::Splitter::new(text, parser::SplitFormat::Anime).collect();
-        let progress: Vec<_> = raw[3].split('/').collect();
-        let maximum = match progress[1] {
-            "?" => SeriesCounter::OnGoing,
-            value => SeriesCounter::Value(value.parse().unwrap()),
+    pub fn parse(text: &str) -> Result<Item, ParseError> {
+        fn value<T: FromStr>(text: &str, (position, token): (usize, &str), expected: Field) -> Result<T, ParseError> {
+            token.parse().map_err(|_| ParseError::new(text, position, token, expected))
+        }
+
+        let mut iter = parser::Splitter::new(text, parser::SplitFormat::Anime);
+        let mut next = |expected: Field| match iter.next() {
+            Some(token) => Ok((iter.position(), token)),
+            None => Err(ParseError::new(text, text.len(), "", expected)),
         };
-        Item {
-            name: raw[0].to_owned(),
-            status: Status::from(raw[1]),
-            progress: progress[0].parse().unwrap(),
-            maximum,
-            rate: raw[5].parse().unwrap(),
+        let (_, name) = next(Field::Name)?;
+        let mut result = Item::empty(&parser::unescape(name));
+        let (position, status) = next(Field::Status)?;
+        result.status = match Status::from(status) {
+            Status::Error => return Err(ParseError::new(text, position, status, Field::Status)),
+            value => value,
+        };
+        let (mut has_progress, mut has_score) = (false, false);
+        while let Ok((position, key)) = next(Field::Key) {
+            match key {
+                "progress" => {
+                    let (position, token) = next(Field::Progress)?;
+                    let (progress, maximum) = token.split_once('/').unwrap_or((token, ""));
+                    result.progress = value(text, (position, progress), Field::Progress)?;
+                    result.maximum = value(text, (position, maximum), Field::Progress)?;
+                    has_progress = true;
+                }
+                "rewatches" => result.rewatches = value(text, next(Field::Rewatches)?, Field::Rewatches)?,
+                "duration" => {
+                    let token = next(Field::Duration)?;
+                    result.duration = Some(value(text, token, Field::Duration)?).filter(|duration| *duration != 0);
+                    if result.duration.is_none() {
+                        return Err(ParseError::new(text, token.0, token.1, Field::Duration));
+                    }
+                }
+                "priority" => {
+                    let token = next(Field::Priority)?;
+                    result.priority = value(text, token, Field::Priority)?;
+                    if result.priority > MAX_PRIORITY {
+                        return Err(ParseError::new(text, token.0, token.1, Field::Priority));
+                    }
+                }
+                "rewatching" => result.rewatching = true,
+                "type" => {
+                    let (position, token) = next(Field::Type)?;
+                    result.kind = match MediaType::from(token) {
+                        MediaType::Error => return Err(ParseError::new(text, position, token, Field::Type)),
+                        value => value,
+                    };
+                }
+                "volumes" => {
+                    let (position, token) = next(Field::Volumes)?;
+                    let (volume, volumes) = token.split_once('/').unwrap_or((token, ""));
+                    result.volume = value(text, (position, volume), Field::Volumes)?;
+                    result.volumes = value(text, (position, volumes), Field::Volumes)?;
+                }
+                "score" => {
+                    let token = next(Field::Score)?;
+                    result.rate = value(text, token, Field::Score)?;
+                    if result.rate > MAX_RATE {
+                        return Err(ParseError::new(text, token.0, token.1, Field::Score));
+                    }
+                    has_score = true;
+                }
+                "started" => result.started = Some(value(text, next(Field::Date)?, Field::Date)?),
+                "finished" => result.finished = Some(value(text, next(Field::Date)?, Field::Date)?),
+                "airing" => result.airing = Some(value(text, next(Field::Airing)?, Field::Airing)?),
+                "tags" => result.tags = parse_tags(&parser::unescape(next(Field::Tags)?.1)),
+                "sequel" | "prequel" | "side" | "main" | "franchise" => {
+                    let token = next(Field::Relation)?;
+                    let id = value(text, token, Field::Relation)?;
+                    match Relation::from(key) {
+                        Some(kind) if id != 0 => result.relations.push((kind, id)),
+                        _ => return Err(ParseError::new(text, token.0, token.1, Field::Relation)),
+                    }
+                }
+                "alias" => result.aliases.push(parser::unescape(next(Field::Alias)?.1)),
+                "custom" => {
+                    let (position, token) = next(Field::Custom)?;
+                    match parse_custom(&parser::unescape(token)) {
+                        Some(field) => result.custom.push(field),
+                        None => return Err(ParseError::new(text, position, token, Field::Custom)),
+                    }
+                }
+                "note" => result.note = parser::unescape(next(Field::Note)?.1),
+                "id" => {
+                    let token = next(Field::Id)?;
+                    result.id = value(text, token, Field::Id)?;
+                    if result.id == 0 {
+                        return Err(ParseError::new(text, token.0, token.1, Field::Id));
+                    }
+                }
+                _ => return Err(ParseError::new(text, position, key, Field::Key)),
+            }
+        }
+        if !has_progress {
+            return Err(ParseError::new(text, text.len(), "", Field::ProgressKey));
+        }
+        if !has_score {
+            return Err(ParseError::new(text, text.len(), "", Field::ScoreKey));
+        }
+        Ok(result)
+    }
+
+    // field names and values as stored in the journal
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("name", self.name.clone()),
+            ("aliases", self.aliases.join("\n")),
+            ("status", self.status.to_string()),
+            ("type", self.kind.to_string()),
+            ("progress", self.progress.to_string()),
+            ("maximum", self.maximum.to_string()),
+            ("volume", self.volume.to_string()),
+            ("volumes", self.volumes.to_string()),
+            ("duration", self.duration.map_or_else(String::new, |duration| duration.to_string())),
+            ("rate", self.rate.to_string()),
+            ("priority", self.priority.to_string()),
+            ("rewatches", self.rewatches.to_string()),
+            ("rewatching", self.rewatching.to_string()),
+            ("started", date_to_string(self.started)),
+            ("finished", date_to_string(self.finished)),
+            ("airing", self.airing.map_or_else(String::new, |airing| airing.to_string())),
+            ("tags", self.tags.join(",")),
+            ("relations", relations_to_string(&self.relations)),
+            (
+                "custom",
+                self.custom.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join("\n"),
+            ),
+            ("note", self.note.clone()),
+        ]
+    }
+
+    pub fn set_field(&mut self, field: &str, value: &str) -> Option<()> {
+        match field {
+            "name" => self.name = value.to_owned(),
+            "aliases" => self.aliases = value.lines().filter(|alias| !alias.is_empty()).map(str::to_owned).collect(),
+            "status" => match Status::from(value) {
+                Status::Error => return None,
+                status => self.status = status,
+            },
+            "type" => match MediaType::from(value) {
+                MediaType::Error => return None,
+                kind => self.kind = kind,
+            },
+            "progress" => self.progress = value.parse().ok()?,
+            "maximum" => self.maximum = value.parse().ok()?,
+            "volume" => self.volume = value.parse().ok()?,
+            "volumes" => self.volumes = value.parse().ok()?,
+            "duration" if value.is_empty() => self.duration = None,
+            "duration" => self.duration = Some(value.parse().ok().filter(|duration| *duration != 0)?),
+            "rate" => self.rate = value.parse().ok().filter(|rate| *rate <= MAX_RATE)?,
+            "priority" => self.priority = value.parse().ok().filter(|priority| *priority <= MAX_PRIORITY)?,
+            "rewatches" => self.rewatches = value.parse().ok()?,
+            "rewatching" => self.rewatching = value.parse().ok()?,
+            "started" => self.started = date_from_str(value)?,
+            "finished" => self.finished = date_from_str(value)?,
+            "airing" if value.is_empty() => self.airing = None,
+            "airing" => self.airing = Some(value.parse().ok()?),
+            "tags" => self.tags = parse_tags(value),
+            "relations" => self.relations = relations_from_str(value)?,
+            "custom" => self.custom = value.lines().map(parse_custom).collect::<Option<_>>()?,
+            "note" => self.note = value.to_owned(),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    pub fn to_line(&self) -> String {
+        let mut result = format!(
+            "\"{}\" {} progress {}/{} score {}",
+            parser::escape(&self.name),
+            self.status,
+            self.progress,
+            self.maximum,
+            self.rate
+        );
+        if self.priority != 0 {
+            result.push_str(&format!(" priority {}", self.priority));
+        }
+        if self.rewatches != 0 {
+            result.push_str(&format!(" rewatches {}", self.rewatches));
+        }
+        if self.rewatching {
+            result.push_str(" rewatching");
+        }
+        if self.kind != MediaType::Tv {
+            result.push_str(&format!(" type {}", self.kind));
+        }
+        if self.volume != 0 || self.volumes != SeriesCounter::OnGoing {
+            result.push_str(&format!(" volumes {}/{}", self.volume, self.volumes));
+        }
+        if let Some(duration) = self.duration {
+            result.push_str(&format!(" duration {}", duration));
+        }
+        for alias in &self.aliases {
+            result.push_str(&format!(" alias \"{}\"", parser::escape(alias)));
+        }
+        if let Some(date) = self.started {
+            result.push_str(&format!(" started {}", date));
+        }
+        if let Some(date) = self.finished {
+            result.push_str(&format!(" finished {}", date));
+        }
+        if let Some(airing) = self.airing {
+            result.push_str(&format!(" airing {}", airing));
+        }
+        if !self.tags.is_empty() {
+            result.push_str(&format!(" tags \"{}\"", parser::escape(&self.tags.join(","))));
+        }
+        for (kind, id) in &self.relations {
+            result.push_str(&format!(" {} {}", kind, id));
+        }
+        for (key, value) in &self.custom {
+            result.push_str(&format!(" custom \"{}\"", parser::escape(&format!("{}={}", key, value))));
+        }
+        if !self.note.is_empty() {
+            result.push_str(&format!(" note \"{}\"", parser::escape(&self.note)));
+        }
+        if self.id != 0 {
+            result.push_str(&format!(" id {}", self.id));
+        }
+        result
+    }
+
+    // item differs from the line it was loaded from
+    pub fn is_changed(&self) -> bool {
+        match self.source.as_deref().map(|s| Item::parse(s.trim_end_matches(['\r', '\n']))) {
+            Some(Ok(origin)) => origin.to_line() != self.to_line(),
+            _ => true,
+        }
+    }
+
+    // name or any of the aliases
+    pub fn has_title(&self, title: &str) -> bool {
+        self.name == title || self.aliases.iter().any(|alias| alias == title)
+    }
+
+    // episodes out by `date`, known ones are never exceeded
+    pub fn aired(&self, date: NaiveDate) -> Option<u16> {
+        let aired = self.airing?.aired(date);
+        match self.maximum {
+            SeriesCounter::Value(maximum) => Some(cmp::min(aired, maximum)),
+            SeriesCounter::OnGoing => Some(aired),
+        }
+    }
+
+    pub fn episode_duration(&self) -> u16 {
+        self.duration.unwrap_or(self.kind.duration())
+    }
+
+    // every finished pass counts the whole series, ongoing ones as far as they are known
+    pub fn watched_minutes(&self) -> u32 {
+        let length = match self.maximum {
+            SeriesCounter::Value(maximum) => maximum,
+            SeriesCounter::OnGoing => self.progress,
+        } as u32;
+        let passes = self.rewatches as u32 + self.rewatching as u32;
+        (passes * length + self.progress as u32) * self.episode_duration() as u32
+    }
+
+    // unknown for ongoing series without an expected number of episodes
+    pub fn remaining_minutes(&self) -> Option<u32> {
+        let total = match (self.maximum, self.airing.map(|airing| airing.total)) {
+            (SeriesCounter::Value(maximum), _) | (_, Some(SeriesCounter::Value(maximum))) => maximum,
+            _ => return None,
+        };
+        Some(total.saturating_sub(self.progress) as u32 * self.episode_duration() as u32)
+    }
+
+    // ongoing series without a schedule may always have new episodes
+    pub fn has_remaining(&self, date: NaiveDate) -> bool {
+        match (self.aired(date), self.maximum) {
+            (Some(aired), _) => aired > self.progress,
+            (None, SeriesCounter::Value(maximum)) => maximum > self.progress,
+            (None, SeriesCounter::OnGoing) => true,
         }
     }
 
+    // the first watch is pass 1, a rewatch in progress already counts
+    pub fn pass(&self) -> u16 {
+        self.rewatches + 1 + self.rewatching as u16
+    }
+
+    // keys are compared as tags are
+    pub fn custom_field(&self, key: &str) -> Option<&str> {
+        let key = key.to_lowercase();
+        self.custom.iter().find(|(other, _)| other.to_lowercase() == key).map(|(_, value)| value.as_str())
+    }
+
+    // an empty value removes the field
+    pub fn set_custom(&mut self, key: &str, value: &str) {
+        let key_lower = key.to_lowercase();
+        let index = self.custom.iter().position(|(other, _)| other.to_lowercase() == key_lower);
+        match (index, value.is_empty()) {
+            (Some(index), true) => {
+                self.custom.remove(index);
+            }
+            (Some(index), false) => self.custom[index].1 = value.to_owned(),
+            (None, true) => {}
+            (None, false) => self.custom.push((key.to_owned(), value.to_owned())),
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = tag.to_lowercase();
+        self.tags.iter().any(|f| f.to_lowercase() == tag)
+    }
+
     pub fn empty(name: &str) -> Item {
-        Item { name: name.to_owned(), status: Status::Plan, progress: 0, maximum: SeriesCounter::OnGoing, rate: 0 }
+        Item {
+            id: 0,
+            name: name.to_owned(),
+            aliases: Vec::new(),
+            status: Status::Plan,
+            kind: MediaType::Tv,
+            progress: 0,
+            maximum: SeriesCounter::OnGoing,
+            volume: 0,
+            volumes: SeriesCounter::OnGoing,
+            duration: None,
+            rate: 0,
+            priority: 0,
+            rewatches: 0,
+            rewatching: false,
+            started: None,
+            finished: None,
+            airing: None,
+            tags: Vec::new(),
+            relations: Vec::new(),
+            custom: Vec::new(),
+            note: String::new(),
+            leading: Vec::new(),
+            source: None,
+        }
     }
 }
 
@@ -116,6 +898,89 @@ impl fmt::Display for Status {
     }
 }
 
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                MediaType::Tv => "tv",
+                MediaType::Movie => "movie",
+                MediaType::Ova => "ova",
+                MediaType::Ona => "ona",
+                MediaType::Special => "special",
+                MediaType::Manga => "manga",
+                MediaType::Novel => "novel",
+                MediaType::Error => "<error>",
+            }
+        )
+    }
+}
+
+impl fmt::Display for Airing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.start, self.interval, self.total)
+    }
+}
+
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Relation::Sequel => "sequel",
+                Relation::Prequel => "prequel",
+                Relation::SideStory => "side",
+                Relation::MainStory => "main",
+                Relation::Franchise => "franchise",
+            }
+        )
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Field::Name => "name",
+                Field::Status => "status",
+                Field::ProgressKey => "`progress` keyword",
+                Field::Progress => "progress value `n/m`",
+                Field::ScoreKey => "`score` keyword",
+                Field::Score => "score value from 0 to 100",
+                Field::OldScore => "score value from 0 to 10",
+                Field::Type => "media type",
+                Field::Volumes => "volumes value `n/m`",
+                Field::Rewatches => "rewatches count",
+                Field::Id => "non-zero id",
+                Field::Date => "date `YYYY-MM-DD`",
+                Field::Tags => "comma separated tags",
+                Field::Note => "quoted note",
+                Field::Alias => "quoted alias",
+                Field::Relation => "non-zero id of the related item",
+                Field::Airing => "airing schedule `YYYY-MM-DD/days/episodes`",
+                Field::Priority => "priority from 0 to 5",
+                Field::Custom => "quoted custom field `key=value`",
+                Field::Duration => "non-zero episode duration in minutes",
+                Field::Key => "field name",
+            }
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.token.is_empty() {
+            write!(f, "line {}, column {}: expected {}", self.line, self.column, self.expected)
+        } else {
+            write!(f, "line {}, column {}: expected {}, found `{}`", self.line, self.column, self.expected, self.token)
+        }
+    }
+}
+
 impl fmt::Display for SeriesCounter {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -124,3 +989,47 @@ impl fmt::Display for SeriesCounter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_from_version_1() {
+        let line = migrate(r#""Fate\Zero" drop progress 3/25 score 7"#, 1).unwrap();
+        assert_eq!(line, r#""Fate\\Zero" drop progress 3/25 score 70"#);
+        let item = Item::parse(&line).unwrap();
+        assert_eq!((item.name.as_str(), item.rate, item.id), (r"Fate\Zero", 70, 0));
+    }
+
+    #[test]
+    fn migrate_from_version_4() {
+        let line = migrate(r#""A\B" complete progress 12/12 score 10 tags "x" id 5"#, 4).unwrap();
+        assert_eq!(line, r#""A\\B" complete progress 12/12 score 100 tags "x" id 5"#);
+        let item = Item::parse(&line).unwrap();
+        assert_eq!((item.name.as_str(), item.rate, item.id), (r"A\B", 100, 5));
+    }
+
+    #[test]
+    fn migrate_from_version_9() {
+        // backslashes are escaped since version 5 and a title may look like a key
+        let line = migrate(r#""score \\ 5" watch progress 1/? score 0 sequel 2 id 1"#, 9).unwrap();
+        assert_eq!(line, r#""score \\ 5" watch progress 1/? score 0 sequel 2 id 1"#);
+        let line = migrate(r#""score" watch progress 1/? score 5 id 1"#, 9).unwrap();
+        assert_eq!(Item::parse(&line).unwrap().rate, 50);
+    }
+
+    #[test]
+    fn migrate_rejects_old_score_above_10() {
+        let error = migrate(r#""A" plan progress 0/? score 11"#, 9).unwrap_err();
+        assert_eq!((error.token.as_str(), error.column, error.expected), ("11", 29, Field::OldScore));
+        assert!(migrate(r#""A" plan progress 0/? score 11"#, 1).is_err());
+    }
+
+    #[test]
+    fn migrate_keeps_current_lines() {
+        let line = r#""A" plan progress 0/? score 85 id 1"#;
+        assert_eq!(migrate(line, 10).unwrap(), line);
+        assert_eq!(migrate(line, FORMAT_VERSION).unwrap(), line);
+    }
+}
@@ -0,0 +1,665 @@
+use crate::base;
+use crate::extra::AnimeBase;
+
+use chrono::NaiveDate;
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use tini::Ini;
+
+// version 2 stores scores on a 100-point scale
+static JSON_VERSION: u32 = 2;
+// custom field with the myanimelist id of an item
+static MAL_ID: &str = "mal";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+    Tsv,
+    Mal,
+}
+
+// table column names of the item fields, set in the `[columns]` section of the config
+pub struct Columns {
+    names: Vec<(&'static str, String)>,
+}
+
+// an imported item with the names of the fields found in the file, the others have default values
+#[derive(Debug, Clone)]
+pub struct Imported {
+    pub item: base::Item,
+    pub fields: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub row: usize,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonList<T> {
+    version: u32,
+    items: Vec<T>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonRelation {
+    #[serde(rename = "type")]
+    kind: String,
+    id: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonCustom {
+    key: String,
+    value: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonAiring {
+    start: NaiveDate,
+    #[serde(default = "default_interval")]
+    interval: u16,
+    // `null` for unknown
+    #[serde(default)]
+    total: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonItem {
+    #[serde(default)]
+    id: u32,
+    name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    aliases: Vec<String>,
+    status: String,
+    #[serde(default = "default_type", rename = "type")]
+    kind: String,
+    #[serde(default)]
+    progress: u16,
+    // `null` for ongoing series
+    #[serde(default)]
+    maximum: Option<u16>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    volume: u16,
+    // `null` for unknown, missing for items without volumes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    volumes: Option<u16>,
+    // minutes, missing for the duration of the media type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    duration: Option<u16>,
+    #[serde(default)]
+    rate: u8,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    priority: u8,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    rewatches: u16,
+    #[serde(default, skip_serializing_if = "is_false")]
+    rewatching: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    started: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    finished: Option<NaiveDate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    airing: Option<JsonAiring>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    relations: Vec<JsonRelation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    custom: Vec<JsonCustom>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    note: String,
+}
+
+fn default_type() -> String {
+    base::MediaType::Tv.to_string()
+}
+
+fn default_interval() -> u16 {
+    7
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl Imported {
+    // only the fields found in the file are set, custom fields are merged by key
+    pub fn merge(&self, item: &mut base::Item) {
+        for (field, value) in self.item.fields() {
+            if !self.fields.contains(&field) {
+                continue;
+            }
+            if field == "custom" {
+                for (key, value) in &self.item.custom {
+                    item.set_custom(key, value);
+                }
+            } else {
+                item.set_field(field, &value).expect("Problem with imported field");
+            }
+        }
+    }
+}
+
+impl Format {
+    pub fn from(s: &str) -> Option<Format> {
+        match s {
+            "j" => Some(Format::Json),
+            "c" => Some(Format::Csv),
+            "t" => Some(Format::Tsv),
+            "m" => Some(Format::Mal),
+            _ => None,
+        }
+    }
+
+    fn delimiter(self) -> u8 {
+        match self {
+            Format::Tsv => b'\t',
+            _ => b',',
+        }
+    }
+}
+
+impl Columns {
+    pub fn from(config: &Ini) -> Columns {
+        let mut fields = vec!["id"];
+        fields.extend(base::Item::empty("").fields().into_iter().map(|(field, _)| field));
+        let names = fields
+            .into_iter()
+            .map(|field| {
+                let name = config.get("columns", field).unwrap_or_else(|| field.to_owned());
+                (field, name)
+            })
+            .collect();
+        Columns { names }
+    }
+
+    fn field(&self, column: &str) -> Option<&'static str> {
+        let column = column.trim();
+        self.names.iter().find(|(_, name)| name.eq_ignore_ascii_case(column)).map(|(field, _)| *field)
+    }
+}
+
+impl From<&base::Item> for JsonItem {
+    fn from(item: &base::Item) -> JsonItem {
+        JsonItem {
+            id: item.id,
+            name: item.name.clone(),
+            aliases: item.aliases.clone(),
+            status: item.status.to_string(),
+            kind: item.kind.to_string(),
+            progress: item.progress,
+            maximum: match item.maximum {
+                base::SeriesCounter::Value(value) => Some(value),
+                base::SeriesCounter::OnGoing => None,
+            },
+            volume: item.volume,
+            volumes: match item.volumes {
+                base::SeriesCounter::Value(value) => Some(value),
+                base::SeriesCounter::OnGoing => None,
+            },
+            duration: item.duration,
+            rate: item.rate,
+            priority: item.priority,
+            rewatches: item.rewatches,
+            rewatching: item.rewatching,
+            started: item.started,
+            finished: item.finished,
+            airing: item.airing.map(|airing| JsonAiring {
+                start: airing.start,
+                interval: airing.interval,
+                total: match airing.total {
+                    base::SeriesCounter::Value(value) => Some(value),
+                    base::SeriesCounter::OnGoing => None,
+                },
+            }),
+            tags: item.tags.clone(),
+            relations: item
+                .relations
+                .iter()
+                .map(|(kind, id)| JsonRelation { kind: kind.to_string(), id: *id })
+                .collect(),
+            custom: item
+                .custom
+                .iter()
+                .map(|(key, value)| JsonCustom { key: key.clone(), value: value.clone() })
+                .collect(),
+            note: item.note.clone(),
+        }
+    }
+}
+
+impl JsonItem {
+    fn into_item(self, row: usize) -> Result<base::Item, Error> {
+        let mut item = base::Item::empty(&self.name);
+        item.id = self.id;
+        item.aliases = self.aliases;
+        item.status = base::Status::from(self.status.as_str());
+        item.kind = base::MediaType::from(self.kind.as_str());
+        item.volume = self.volume;
+        item.volumes = match self.volumes {
+            Some(value) => base::SeriesCounter::Value(value),
+            None => base::SeriesCounter::OnGoing,
+        };
+        item.progress = self.progress;
+        item.maximum = match self.maximum {
+            Some(value) => base::SeriesCounter::Value(value),
+            None => base::SeriesCounter::OnGoing,
+        };
+        item.duration = self.duration;
+        item.rate = self.rate;
+        item.priority = self.priority;
+        item.rewatches = self.rewatches;
+        item.rewatching = self.rewatching;
+        item.started = self.started;
+        item.finished = self.finished;
+        item.airing = self.airing.map(|airing| base::Airing {
+            start: airing.start,
+            interval: airing.interval,
+            total: match airing.total {
+                Some(value) => base::SeriesCounter::Value(value),
+                None => base::SeriesCounter::OnGoing,
+            },
+        });
+        item.tags = self.tags;
+        item.note = self.note;
+        for field in self.custom {
+            let text = format!("{}={}", field.key, field.value);
+            match base::parse_custom(&text) {
+                Some(custom) if custom == (field.key.clone(), field.value.clone()) => item.custom.push(custom),
+                _ => return Err(Error { row, message: format!("invalid custom field `{}`", text) }),
+            }
+        }
+        for relation in self.relations {
+            match base::Relation::from(&relation.kind) {
+                Some(kind) => item.relations.push((kind, relation.id)),
+                None => return Err(Error { row, message: format!("invalid relation `{}`", relation.kind) }),
+            }
+        }
+        validate(row, item)
+    }
+}
+
+// imported items pass the same checks as the lines of the list file
+fn validate(row: usize, item: base::Item) -> Result<base::Item, Error> {
+    match base::Item::parse(&item.to_line()) {
+        Ok(_) if item.name.is_empty() => Err(Error { row, message: "expected non-empty name".to_owned() }),
+        Ok(_) => Ok(item),
+        Err(err) if err.token.is_empty() => Err(Error { row, message: format!("expected {}", err.expected) }),
+        Err(err) => Err(Error { row, message: format!("expected {}, found `{}`", err.expected, err.token) }),
+    }
+}
+
+fn export_table(format: Format, base: &AnimeBase, columns: &Columns) -> Result<String, csv::Error> {
+    let mut writer = csv::WriterBuilder::new().delimiter(format.delimiter()).from_writer(Vec::new());
+    writer.write_record(columns.names.iter().map(|(_, name)| name))?;
+    for item in &base.list {
+        let mut values = vec![("id", item.id.to_string())];
+        values.extend(item.fields());
+        let record = columns.names.iter().map(|(field, _)| match values.iter().find(|(f, _)| f == field) {
+            Some((_, value)) => value.as_str(),
+            None => "",
+        });
+        writer.write_record(record)?;
+    }
+    let buffer = writer.into_inner().map_err(|err| err.into_error())?;
+    Ok(String::from_utf8(buffer).expect("Table is not valid UTF-8"))
+}
+
+// empty cells keep the default value of the field
+fn import_table(format: Format, text: &str, columns: &Columns) -> (Vec<Imported>, Vec<Error>) {
+    let mut result = Vec::new();
+    let mut errors = Vec::new();
+    let mut reader =
+        csv::ReaderBuilder::new().delimiter(format.delimiter()).flexible(true).from_reader(text.as_bytes());
+    let header: Vec<_> = match reader.headers() {
+        Ok(header) => header.iter().map(|column| columns.field(column)).collect(),
+        Err(err) => return (result, vec![Error { row: 0, message: err.to_string() }]),
+    };
+    if !header.contains(&Some("name")) {
+        return (result, vec![Error { row: 1, message: "expected name column".to_owned() }]);
+    }
+    for (row, record) in reader.records().enumerate() {
+        // header is the first row
+        let row = row + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                errors.push(Error { row, message: err.to_string() });
+                continue;
+            }
+        };
+        let mut item = base::Item::empty("");
+        let mut fields = Vec::new();
+        let mut error = None;
+        for (field, value) in header.iter().zip(record.iter()) {
+            let value = value.trim();
+            // a column of the row is found in the file even when the cell is empty
+            if let Some(field) = field.filter(|field| *field != "id") {
+                fields.push(field);
+            }
+            let is_set = match (field, value) {
+                (_, "") | (None, _) => Some(()),
+                (Some("id"), _) => value.parse().ok().map(|id| item.id = id),
+                (Some("status"), _) => item.set_field("status", &value.to_lowercase()),
+                (Some(field), _) => item.set_field(field, value),
+            };
+            if is_set.is_none() {
+                error = Some(Error { row, message: format!("invalid {} `{}`", field.unwrap_or_default(), value) });
+                break;
+            }
+        }
+        match error.map_or_else(|| validate(row, item), Err) {
+            Ok(item) => result.push(Imported { item, fields }),
+            Err(err) => errors.push(err),
+        }
+    }
+    (result, errors)
+}
+
+fn mal_status(status: base::Status) -> &'static str {
+    match status {
+        base::Status::Complete => "Completed",
+        base::Status::Watch => "Watching",
+        base::Status::Hold => "On-Hold",
+        base::Status::Drop => "Dropped",
+        base::Status::Plan | base::Status::Error => "Plan to Watch",
+    }
+}
+
+fn from_mal_status(status: &str) -> base::Status {
+    match status.to_lowercase().as_str() {
+        "completed" => base::Status::Complete,
+        "watching" => base::Status::Watch,
+        "on-hold" => base::Status::Hold,
+        "dropped" => base::Status::Drop,
+        "plan to watch" => base::Status::Plan,
+        _ => base::Status::Error,
+    }
+}
+
+fn mal_type(kind: base::MediaType) -> &'static str {
+    match kind {
+        base::MediaType::Tv | base::MediaType::Error => "TV",
+        base::MediaType::Movie => "Movie",
+        base::MediaType::Ova => "OVA",
+        base::MediaType::Ona => "ONA",
+        base::MediaType::Special => "Special",
+        base::MediaType::Manga => "Manga",
+        base::MediaType::Novel => "Light Novel",
+    }
+}
+
+// myanimelist has three priorities
+fn mal_priority(priority: u8) -> &'static str {
+    match priority {
+        0..=1 => "LOW",
+        2..=3 => "MEDIUM",
+        _ => "HIGH",
+    }
+}
+
+fn from_mal_priority(priority: &str) -> u8 {
+    match priority.to_uppercase().as_str() {
+        "MEDIUM" => 3,
+        "HIGH" => base::MAX_PRIORITY,
+        _ => 0,
+    }
+}
+
+// item field set by a tag of the myanimelist export
+fn mal_field(tag: &str) -> Option<&'static str> {
+    match tag {
+        "series_animedb_id" => Some("custom"),
+        "series_title" => Some("name"),
+        "series_episodes" => Some("maximum"),
+        "series_type" => Some("type"),
+        "series_volumes" => Some("volumes"),
+        "my_read_volumes" => Some("volume"),
+        "my_watched_episodes" => Some("progress"),
+        "my_score" => Some("rate"),
+        "my_priority" => Some("priority"),
+        "my_times_watched" => Some("rewatches"),
+        "my_rewatching" => Some("rewatching"),
+        "my_start_date" => Some("started"),
+        "my_finish_date" => Some("finished"),
+        "my_tags" => Some("tags"),
+        "my_comments" => Some("note"),
+        "my_status" => Some("status"),
+        _ => None,
+    }
+}
+
+fn mal_date(date: Option<NaiveDate>) -> String {
+    date.map_or_else(|| "0000-00-00".to_owned(), |date| date.to_string())
+}
+
+// the myanimelist id is kept in the `mal` custom field, 0 episodes is an unknown length,
+// manga and novels belong to a separate manga list and are left out
+fn export_mal(base: &AnimeBase) -> String {
+    let items: Vec<_> = base.list.iter().filter(|item| !item.kind.is_reading()).collect();
+    let mut result = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n<myanimelist>\n");
+    result.push_str("  <myinfo>\n    <user_export_type>1</user_export_type>\n");
+    result.push_str(&format!("    <user_total_anime>{}</user_total_anime>\n  </myinfo>\n", items.len()));
+    for item in items {
+        let mal_id: u32 = item.custom_field(MAL_ID).and_then(|value| value.parse().ok()).unwrap_or(0);
+        result.push_str(&format!("  <anime>\n    <series_animedb_id>{}</series_animedb_id>\n", mal_id));
+        result.push_str(&format!("    <series_title>{}</series_title>\n", escape(item.name.as_str())));
+        result.push_str(&format!("    <series_type>{}</series_type>\n", mal_type(item.kind)));
+        result.push_str(&format!("    <series_episodes>{}</series_episodes>\n", item.maximum.get()));
+        result.push_str(&format!("    <my_watched_episodes>{}</my_watched_episodes>\n", item.progress));
+        result.push_str(&format!("    <my_start_date>{}</my_start_date>\n", mal_date(item.started)));
+        result.push_str(&format!("    <my_finish_date>{}</my_finish_date>\n", mal_date(item.finished)));
+        result.push_str(&format!("    <my_score>{}</my_score>\n", (item.rate + 5) / 10));
+        result.push_str(&format!("    <my_priority>{}</my_priority>\n", mal_priority(item.priority)));
+        result.push_str(&format!("    <my_times_watched>{}</my_times_watched>\n", item.rewatches));
+        result.push_str(&format!("    <my_rewatching>{}</my_rewatching>\n", item.rewatching as u8));
+        result.push_str(&format!("    <my_tags>{}</my_tags>\n", escape(item.tags.join(",").as_str())));
+        result.push_str(&format!("    <my_comments>{}</my_comments>\n", escape(item.note.as_str())));
+        result.push_str(&format!("    <my_status>{}</my_status>\n  </anime>\n", mal_status(item.status)));
+    }
+    result.push_str("</myanimelist>\n");
+    result
+}
+
+fn import_mal(text: &str) -> (Vec<Imported>, Vec<Error>) {
+    let mut result = Vec::new();
+    let mut errors = Vec::new();
+    let mut reader = Reader::from_str(text);
+    reader.config_mut().trim_text(true);
+    let mut row = 0;
+    let mut tag = String::new();
+    let mut item = None;
+    let mut fields = Vec::new();
+    let mut error = None;
+    loop {
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(err) => {
+                errors.push(Error { row: 0, message: format!("{} at byte {}", err, reader.error_position()) });
+                break;
+            }
+        };
+        let value = match event {
+            Event::Start(e) if e.name().as_ref() == b"anime" => {
+                row += 1;
+                item = Some(base::Item::empty(""));
+                fields.clear();
+                error = None;
+                continue;
+            }
+            Event::Start(e) => {
+                tag = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                continue;
+            }
+            Event::End(e) if e.name().as_ref() == b"anime" => {
+                if let Some(item) = item.take() {
+                    match error.take().map_or_else(|| validate(row, item), Err) {
+                        Ok(item) => result.push(Imported { item, fields: fields.clone() }),
+                        Err(err) => errors.push(err),
+                    }
+                }
+                continue;
+            }
+            Event::End(_) => {
+                tag.clear();
+                continue;
+            }
+            Event::Text(e) => e.unescape().map(|value| value.into_owned()).map_err(|err| err.to_string()),
+            Event::CData(e) => e.decode().map(|value| value.into_owned()).map_err(|err| err.to_string()),
+            Event::Eof => break,
+            _ => continue,
+        };
+        let (item, value) = match (item.as_mut(), value) {
+            (Some(item), Ok(value)) => (item, value),
+            (Some(_), Err(err)) => {
+                error = Some(Error { row, message: err });
+                continue;
+            }
+            (None, _) => continue,
+        };
+        let value = value.trim();
+        let is_set = match tag.as_str() {
+            "series_animedb_id" => value.parse::<u32>().ok().map(|value| {
+                if value != 0 {
+                    item.set_custom(MAL_ID, &value.to_string());
+                }
+            }),
+            "series_title" => {
+                item.name = value.to_owned();
+                Some(())
+            }
+            "series_episodes" => value.parse().ok().map(|value| {
+                item.maximum = match value {
+                    0 => base::SeriesCounter::OnGoing,
+                    value => base::SeriesCounter::Value(value),
+                }
+            }),
+            // types unknown here like `Music` stay tv
+            "series_type" => match value.to_lowercase().as_str() {
+                "light novel" => item.set_field("type", "novel"),
+                kind => item.set_field("type", kind).or(Some(())),
+            },
+            "series_volumes" => value.parse().ok().map(|value| {
+                item.volumes = match value {
+                    0 => base::SeriesCounter::OnGoing,
+                    value => base::SeriesCounter::Value(value),
+                }
+            }),
+            "my_read_volumes" => value.parse().ok().map(|value| item.volume = value),
+            "my_watched_episodes" => value.parse().ok().map(|value| item.progress = value),
+            "my_score" => value.parse::<u8>().ok().filter(|value| *value <= 10).map(|value| item.rate = value * 10),
+            "my_priority" => {
+                item.priority = from_mal_priority(value);
+                Some(())
+            }
+            "my_times_watched" => value.parse().ok().map(|value| item.rewatches = value),
+            "my_rewatching" => value.parse::<u8>().ok().map(|value| item.rewatching = value != 0),
+            "my_start_date" | "my_finish_date" if value == "0000-00-00" => Some(()),
+            "my_start_date" => item.set_field("started", value),
+            "my_finish_date" => item.set_field("finished", value),
+            "my_tags" => item.set_field("tags", value),
+            "my_comments" => item.set_field("note", value),
+            "my_status" => {
+                item.status = from_mal_status(value);
+                Some(())
+            }
+            _ => Some(()),
+        };
+        match (is_set, mal_field(&tag)) {
+            (Some(()), Some(field)) if !fields.contains(&field) => fields.push(field),
+            (Some(()), _) => {}
+            (None, _) if error.is_none() => {
+                error = Some(Error { row, message: format!("invalid {} `{}`", tag, value) })
+            }
+            (None, _) => {}
+        }
+    }
+    (result, errors)
+}
+
+// items the format cannot hold, they are left out of the export
+pub fn unsupported(format: Format, base: &AnimeBase) -> Vec<&base::Item> {
+    match format {
+        Format::Mal => base.list.iter().filter(|item| item.kind.is_reading()).collect(),
+        Format::Json | Format::Csv | Format::Tsv => Vec::new(),
+    }
+}
+
+pub fn export(format: Format, base: &AnimeBase, columns: &Columns) -> String {
+    match format {
+        Format::Mal => export_mal(base),
+        Format::Csv | Format::Tsv => export_table(format, base, columns).expect("Cannot serialize list"),
+        Format::Json => {
+            let items: Vec<_> = base.list.iter().map(JsonItem::from).collect();
+            let list = JsonList { version: JSON_VERSION, items };
+            serde_json::to_string_pretty(&list).expect("Cannot serialize list")
+        }
+    }
+}
+
+pub fn import(format: Format, text: &str, columns: &Columns) -> (Vec<Imported>, Vec<Error>) {
+    let mut result = Vec::new();
+    let mut errors = Vec::new();
+    match format {
+        Format::Csv | Format::Tsv => return import_table(format, text, columns),
+        Format::Mal => return import_mal(text),
+        // items are checked one by one, so a bad item does not hide the others
+        Format::Json => match serde_json::from_str::<JsonList<serde_json::Value>>(text) {
+            Ok(list) if list.version > JSON_VERSION => {
+                errors.push(Error { row: 0, message: format!("unsupported version {}", list.version) })
+            }
+            Ok(list) => {
+                let names: Vec<_> = base::Item::empty("").fields().into_iter().map(|(field, _)| field).collect();
+                for (row, value) in list.items.into_iter().enumerate() {
+                    // keys of the object are the names of the fields
+                    let fields = match value.as_object() {
+                        Some(object) => names.iter().copied().filter(|name| object.contains_key(*name)).collect(),
+                        None => Vec::new(),
+                    };
+                    let item = serde_json::from_value::<JsonItem>(value)
+                        .map_err(|err| Error { row: row + 1, message: err.to_string() })
+                        .and_then(|mut item| {
+                            // a 10-point score before version 2
+                            if list.version < 2 {
+                                if item.rate > 10 {
+                                    return Err(Error {
+                                        row: row + 1,
+                                        message: format!("invalid rate `{}`", item.rate),
+                                    });
+                                }
+                                item.rate *= 10;
+                            }
+                            Ok(item)
+                        })
+                        .and_then(|item| item.into_item(row + 1));
+                    match item {
+                        Ok(item) => result.push(Imported { item, fields }),
+                        Err(err) => errors.push(err),
+                    }
+                }
+            }
+            Err(err) => errors.push(Error { row: 0, message: err.to_string() }),
+        },
+    }
+    (result, errors)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.row == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "row {}: {}", self.row, self.message)
+        }
+    }
+}
@@ -1,22 +1,64 @@
 use crate::base;
+use crate::exchange;
+use crate::journal::Change;
 use crate::parser;
 
+use chrono::{Datelike, Local, NaiveDate};
 use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::fs::File;
 use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::OnceLock;
 
-#[derive(Debug, Clone, Copy)]
+// custom fields shown by `format`, `*` shows all of them
+static SHOWN_CUSTOM: OnceLock<Vec<String>> = OnceLock::new();
+
+// `7`, `!=7`, `<7`, `<=7`, `>7`, `>=7` or `7..10` with any of the bounds, a value
+// is a range of its own as a score stands for the stored scores shown as it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compare<T> {
+    Within(T, T),
+    Outside(T, T),
+    Below(T),
+    AtMost(T),
+    Above(T),
+    AtLeast(T),
+}
+
+#[derive(Debug, Clone)]
 pub enum ParamType {
+    Id(Compare<u32>),
     Status(base::Status),
-    Progress(u16),
-    Maximum(base::SeriesCounter),
-    Rate(u8),
+    Progress(Compare<u16>),
+    // known maxima only
+    Maximum(Compare<u16>),
+    // `?` or `!=?`
+    OnGoing(bool),
+    Rate(Compare<u8>),
+    Started(base::DateRange),
+    Finished(base::DateRange),
+    Kind(base::MediaType),
+    Tag(String),
+    NoTag(String),
+    // custom field with any or the given value
+    Custom(String, Option<String>),
+    NoCustom(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    Merge,
+    Replace,
+    DryRun,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum ErrorStatus {
     IntParseError,
+    DateParseError,
+    ScoreParseError,
+    CustomParseError,
     EmptyFieldError,
     UnknownCommand,
 }
@@ -32,107 +74,708 @@ pub enum ExecCmd {
     FindParam(ParamType),
     FilterParam(ParamType),
     Maximum(base::SeriesCounter),
-    Rename(String),
+    // keeps the old name as an alias
+    Rename(String, bool),
     Progress(u16),
     Status(base::Status),
     Rate(u8),
+    Kind(base::MediaType),
+    Rewatch,
+    StopRewatch,
+    Rewatches(u16),
+    Volume(u16),
+    VolumeMaximum(base::SeriesCounter),
+    VolumeIncrement(u16),
+    VolumeDecrement(u16),
+    Started(Option<NaiveDate>),
+    Finished(Option<NaiveDate>),
+    Airing(Option<NaiveDate>),
+    AiringInterval(u16),
+    AiringTotal(base::SeriesCounter),
+    Available,
+    Priority(u8),
+    Duration(Option<u16>),
+    WatchTime,
+    Queue,
+    Next(bool),
+    AddTags(Vec<String>),
+    RemoveTags(Vec<String>),
+    Link(base::Relation, u32),
+    Unlink(u32),
+    NextInFranchise,
+    AddAlias(String),
+    RemoveAlias(String),
+    Custom(String, String),
+    Note(String),
+    EditNote,
     Write,
+    Restore(usize),
+    Export(exchange::Format, String),
+    Import(exchange::Format, ImportMode, String),
+    Journal,
+    Undo(usize),
+    Redo(usize),
+    History,
+    Recent(usize),
     Error(ErrorStatus),
 }
 
+impl<T: PartialOrd + Copy> Compare<T> {
+    fn parse(s: &str, value: impl Fn(&str) -> Option<(T, T)>) -> Option<Compare<T>> {
+        if let Some(range) = s.split_once("..") {
+            return match range {
+                ("", "") => None,
+                ("", to) => Some(Compare::AtMost(value(to)?.1)),
+                (from, "") => Some(Compare::AtLeast(value(from)?.0)),
+                (from, to) => Some(Compare::Within(value(from)?.0, value(to)?.1)),
+            };
+        }
+        let operators = ["<=", ">=", "!=", "<", ">"];
+        let (operator, s) = operators.iter().find_map(|op| s.strip_prefix(op).map(|s| (*op, s))).unwrap_or(("", s));
+        let (low, high) = value(s)?;
+        Some(match operator {
+            "<=" => Compare::AtMost(high),
+            ">=" => Compare::AtLeast(low),
+            "!=" => Compare::Outside(low, high),
+            "<" => Compare::Below(low),
+            ">" => Compare::Above(high),
+            _ => Compare::Within(low, high),
+        })
+    }
+
+    pub fn matches(&self, value: T) -> bool {
+        match *self {
+            Compare::Within(low, high) => low <= value && value <= high,
+            Compare::Outside(low, high) => value < low || high < value,
+            Compare::Below(low) => value < low,
+            Compare::AtMost(high) => value <= high,
+            Compare::Above(high) => value > high,
+            Compare::AtLeast(low) => value >= low,
+        }
+    }
+}
+
+impl ParamType {
+    pub fn matches(&self, item: &base::Item) -> bool {
+        match self {
+            ParamType::Id(compare) => compare.matches(item.id),
+            ParamType::Status(value) => item.status == *value,
+            ParamType::Progress(compare) => compare.matches(item.progress),
+            ParamType::Maximum(compare) => {
+                matches!(item.maximum, base::SeriesCounter::Value(maximum) if compare.matches(maximum))
+            }
+            ParamType::OnGoing(value) => (item.maximum == base::SeriesCounter::OnGoing) == *value,
+            ParamType::Rate(compare) => compare.matches(item.rate),
+            ParamType::Started(range) => range.contains(item.started),
+            ParamType::Finished(range) => range.contains(item.finished),
+            ParamType::Kind(value) => item.kind == *value,
+            ParamType::Tag(tag) => item.has_tag(tag),
+            ParamType::NoTag(tag) => !item.has_tag(tag),
+            ParamType::Custom(key, None) => item.custom_field(key).is_some(),
+            ParamType::Custom(key, Some(value)) => {
+                item.custom_field(key).is_some_and(|other| other.to_lowercase() == value.to_lowercase())
+            }
+            ParamType::NoCustom(key) => item.custom_field(key).is_none(),
+        }
+    }
+
+    fn id(s: &str) -> Result<ParamType, ErrorStatus> {
+        Compare::parse(s, number).map(ParamType::Id).ok_or(ErrorStatus::IntParseError)
+    }
+
+    fn progress(s: &str) -> Result<ParamType, ErrorStatus> {
+        Compare::parse(s, number).map(ParamType::Progress).ok_or(ErrorStatus::IntParseError)
+    }
+
+    fn maximum(s: &str) -> Result<ParamType, ErrorStatus> {
+        match s {
+            "?" => Ok(ParamType::OnGoing(true)),
+            "!=?" => Ok(ParamType::OnGoing(false)),
+            _ => Compare::parse(s, number).map(ParamType::Maximum).ok_or(ErrorStatus::IntParseError),
+        }
+    }
+
+    // scores in the configured system
+    fn rate(s: &str) -> Result<ParamType, ErrorStatus> {
+        let scoring = base::Scoring::current();
+        Compare::parse(s, |s| scoring.range(s)).map(ParamType::Rate).ok_or(ErrorStatus::ScoreParseError)
+    }
+
+    // `tag` or `!tag` for items without it
+    fn tag(s: &str) -> ParamType {
+        match s.strip_prefix('!') {
+            Some(tag) => ParamType::NoTag(tag.trim().to_owned()),
+            None => ParamType::Tag(s.trim().to_owned()),
+        }
+    }
+
+    // `key`, `key=value` or `!key` for items without the field
+    fn custom(s: &str) -> ParamType {
+        match (s.strip_prefix('!'), base::parse_custom(s)) {
+            (Some(key), _) => ParamType::NoCustom(key.trim().to_owned()),
+            (None, Some((key, value))) => ParamType::Custom(key, Some(value)),
+            (None, None) => ParamType::Custom(s.trim().to_owned(), None),
+        }
+    }
+}
+
+fn number<T: FromStr + Copy>(s: &str) -> Option<(T, T)> {
+    s.parse().ok().map(|value| (value, value))
+}
+
+// `2d 5h 30m`
+pub fn format_minutes(minutes: u32) -> String {
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+// empty value is today, `?` removes the date
+fn parse_date(s: &str) -> Result<Option<NaiveDate>, ErrorStatus> {
+    match s {
+        "" => Ok(Some(Local::now().date_naive())),
+        "?" => Ok(None),
+        _ => s.parse().map(Some).map_err(|_| ErrorStatus::DateParseError),
+    }
+}
+
+// watched minutes of a list and minutes left of watched and planned items
+pub struct WatchTime {
+    pub total: u32,
+    pub by_status: Vec<(base::Status, u32)>,
+    // tags in lowercase, the longest first
+    pub by_tag: Vec<(String, u32)>,
+    pub remaining: Vec<(base::Status, u32)>,
+    // watched and planned ongoing series of unknown length
+    pub unknown: usize,
+}
+
 pub struct AnimeBase {
     pub list: Vec<base::Item>,
+    pub trailing: Vec<String>,
+    pub changes: Vec<Change>,
+    // format version of the loaded list
+    pub version: u32,
+    pub next_id: u32,
+    pub id_len: usize,
     pub name_len: usize,
     pub series_len: usize,
 }
 
 impl AnimeBase {
     pub fn new() -> AnimeBase {
-        AnimeBase { list: Vec::new(), name_len: 0, series_len: 0 }
+        AnimeBase {
+            list: Vec::new(),
+            trailing: Vec::new(),
+            changes: Vec::new(),
+            version: base::FORMAT_VERSION,
+            next_id: 1,
+            id_len: 0,
+            name_len: 0,
+            series_len: 0,
+        }
     }
 
-    pub fn push(&mut self, item: base::Item) {
+    // comments, blank and invalid lines are kept in front of the next item
+    pub fn parse(buffer: &str) -> (AnimeBase, Vec<base::ParseError>) {
+        let mut result = AnimeBase::new();
+        let mut errors = Vec::new();
+        let mut leading = Vec::new();
+        let mut ids = HashSet::new();
+        let header = buffer.lines().next().and_then(base::parse_header);
+        result.version = header.unwrap_or(1);
+        for (number, string) in buffer.split_inclusive('\n').enumerate().skip(header.map_or(0, |_| 1)) {
+            let line = string.trim_end_matches(['\r', '\n']);
+            if let Some(id) = base::parse_next_id(line) {
+                result.next_id = cmp::max(result.next_id, id);
+                continue;
+            }
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                leading.push(string.to_owned());
+                continue;
+            }
+            let line = match base::migrate(line, result.version) {
+                Ok(line) => line,
+                Err(err) => {
+                    errors.push(err.at_line(number + 1));
+                    leading.push(string.to_owned());
+                    continue;
+                }
+            };
+            let line = &line;
+            match base::Item::parse(line) {
+                Ok(item) if item.id != 0 && !ids.insert(item.id) => {
+                    let id = item.id.to_string();
+                    let position = line.rfind(&id).unwrap_or(0);
+                    errors.push(base::ParseError::new(line, position, &id, base::Field::Id).at_line(number + 1));
+                    leading.push(string.to_owned());
+                }
+                Ok(mut item) => {
+                    item.leading = std::mem::take(&mut leading);
+                    item.source = Some(string.to_owned());
+                    result.push(item);
+                }
+                Err(err) => {
+                    errors.push(err.at_line(number + 1));
+                    leading.push(string.to_owned());
+                }
+            }
+        }
+        result.trailing = leading;
+        // items from older lists get their ids on load and keep them on the next write
+        for index in 0..result.list.len() {
+            if result.list[index].id == 0 {
+                result.list[index].id = result.next_id;
+                result.next_id += 1;
+            }
+            result.measure_id(result.list[index].id);
+        }
+        (result, errors)
+    }
+
+    fn measure_id(&mut self, id: u32) {
+        self.next_id = cmp::max(self.next_id, id.saturating_add(1));
+        self.id_len = cmp::max(self.id_len, id.to_string().len());
+    }
+
+    fn measure(&mut self, item: &base::Item) {
+        self.measure_id(item.id);
         self.name_len = cmp::max(item.name.len(), self.name_len);
         let curr = (f32::log10(cmp::max(item.maximum.get(), item.progress) as f32)).round() as u16;
         self.series_len = cmp::max(self.series_len, curr as usize);
+    }
+
+    pub fn push(&mut self, item: base::Item) {
+        self.measure(&item);
         self.list.push(item);
     }
 
-    pub fn append(&mut self, name: &str) -> usize {
-        self.push(base::Item::empty(name));
-        self.list.len() - 1
+    fn insert(&mut self, index: usize, item: base::Item) {
+        self.measure(&item);
+        self.list.insert(index, item);
     }
 
-    pub fn format(&self, item: &base::Item) -> String {
-        format!(
-            "'{:>5$}', status: {:>8}, progress: {:>6$} / {:>6$}, rate: {:>2} / 10",
-            item.name, item.status, item.progress, item.maximum, item.rate, self.name_len, self.series_len
-        )
+    pub fn append(&mut self, name: &str) -> u32 {
+        self.add(base::Item::empty(name))
     }
 
-    pub fn format_by_index(&self, index: usize) -> String {
-        let item = self.list.get(index).unwrap();
-        self.format(item)
+    // keeps the id of the item if it is still free
+    pub fn add(&mut self, mut item: base::Item) -> u32 {
+        if item.id == 0 || self.get_item(item.id).is_some() {
+            item.id = self.next_id;
+        }
+        let id = item.id;
+        self.changes.push(Change::Insert { index: self.list.len(), line: item.to_line(), leading: Vec::new() });
+        self.push(item);
+        id
     }
 
-    pub fn write_to_file(&self, output: &mut File) -> Result<(), io::Error> {
-        let mut result = String::new();
-        for item in &(self.list) {
+    // items are matched by id and name first, then by name only and by alias, merge only
+    // sets the fields found in the file, replace also removes every item that is missing from the import
+    pub fn import(&mut self, items: Vec<exchange::Imported>, mode: ImportMode) -> Vec<String> {
+        let mut result = Vec::new();
+        let mut matched = HashSet::new();
+        for imported in items {
+            let item = &imported.item;
+            let target = self
+                .list
+                .iter()
+                .find(|f| f.id == item.id && f.name == item.name && !matched.contains(&f.id))
+                .or_else(|| self.list.iter().find(|f| f.name == item.name && !matched.contains(&f.id)))
+                .or_else(|| self.list.iter().find(|f| f.has_title(&item.name) && !matched.contains(&f.id)))
+                .cloned();
+            match target {
+                Some(target) => {
+                    matched.insert(target.id);
+                    let mut item = match mode {
+                        ImportMode::Replace => imported.item,
+                        ImportMode::Merge | ImportMode::DryRun => {
+                            let mut merged = target.clone();
+                            imported.merge(&mut merged);
+                            merged
+                        }
+                    };
+                    item.id = target.id;
+                    if item.fields() == target.fields() {
+                        continue;
+                    }
+                    result.push(format!("update: {}", self.format(&item)));
+                    if mode != ImportMode::DryRun {
+                        self.update(target.id, &item);
+                    }
+                }
+                None => {
+                    result.push(format!("   add: {}", self.format(&imported.item)));
+                    if mode != ImportMode::DryRun {
+                        matched.insert(self.add(imported.item));
+                    }
+                }
+            }
+        }
+        if mode == ImportMode::Replace {
+            let ids: Vec<_> = self.list.iter().map(|f| f.id).filter(|id| !matched.contains(id)).collect();
+            for id in ids {
+                if let Some(item) = self.remove(id) {
+                    result.push(format!("delete: {}", self.format(&item)));
+                }
+            }
+        }
+        result
+    }
+
+    pub fn index_of(&self, id: u32) -> Option<usize> {
+        self.list.iter().position(|item| item.id == id)
+    }
+
+    pub fn format(&self, item: &base::Item) -> String {
+        let unit = if item.kind.is_reading() { "chapters" } else { "progress" };
+        let mut result = format!(
+            "#{:<7$} '{:>8$}', status: {:>8}, {}: {:>9$} / {:>9$}, rate: {}",
+            item.id,
+            item.name,
+            item.status,
+            unit,
+            item.progress,
+            item.maximum,
+            base::Scoring::current().format(item.rate),
+            self.id_len,
+            self.name_len,
+            self.series_len
+        );
+        if item.kind.is_reading() || item.volume != 0 || item.volumes != base::SeriesCounter::OnGoing {
+            result.push_str(&format!(", volumes: {} / {}", item.volume, item.volumes));
+        }
+        if item.kind != base::MediaType::Tv {
+            result.push_str(&format!(", type: {}", item.kind));
+        }
+        if item.priority != 0 {
+            result.push_str(&format!(", priority: {}", item.priority));
+        }
+        if let Some(duration) = item.duration {
+            result.push_str(&format!(", duration: {} min", duration));
+        }
+        if item.rewatching {
+            result.push_str(&format!(", rewatching: pass {}", item.pass()));
+        } else if item.rewatches != 0 {
+            result.push_str(&format!(", rewatches: {}", item.rewatches));
+        }
+        if item.started.is_some() || item.finished.is_some() {
+            let date = |date: Option<NaiveDate>| date.map_or_else(|| "?".to_owned(), |date| date.to_string());
+            result.push_str(&format!(", dates: {} - {}", date(item.started), date(item.finished)));
+        }
+        if let Some(airing) = item.airing {
+            let aired = item.aired(Local::now().date_naive()).unwrap_or_default();
             result.push_str(&format!(
-                "\"{}\" {} progress {}/{} score {}\n",
-                item.name, item.status, item.progress, item.maximum, item.rate
+                ", airing: {} from {} every {} days, {} / {} aired",
+                airing.start.weekday(),
+                airing.start,
+                airing.interval,
+                aired,
+                airing.total
             ));
         }
+        if !item.aliases.is_empty() {
+            result.push_str(&format!(", aliases: {}", item.aliases.join("; ")));
+        }
+        if !item.tags.is_empty() {
+            result.push_str(&format!(", tags: {}", item.tags.join(", ")));
+        }
+        let shown = SHOWN_CUSTOM.get().map_or(&[][..], Vec::as_slice);
+        for (key, value) in &item.custom {
+            if shown.iter().any(|shown| shown == "*" || shown.to_lowercase() == key.to_lowercase()) {
+                result.push_str(&format!(", {}: {}", key, value));
+            }
+        }
+        // only the first line of a note fits
+        match item.note.split_once('\n') {
+            Some((line, _)) => result.push_str(&format!(", note: {}...", line)),
+            None if !item.note.is_empty() => result.push_str(&format!(", note: {}", item.note)),
+            None => {}
+        }
+        result
+    }
+
+    // related items of a found item, missing ones are skipped
+    pub fn format_related(&self, item: &base::Item) -> Vec<String> {
+        let related = item.relations.iter().filter_map(|(kind, id)| self.get_item(*id).map(|other| (kind, other)));
+        related.map(|(kind, other)| format!("{:>9}: {}", kind.to_string(), self.format(other))).collect()
+    }
+
+    pub fn format_by_id(&self, id: u32) -> Option<String> {
+        self.get_item(id).map(|item| self.format(item))
+    }
+
+    // for reports about items that may be gone already
+    pub fn format_or_id(&self, id: u32) -> String {
+        self.format_by_id(id).unwrap_or_else(|| format!("#{} (missing)", id))
+    }
+
+    // removal keeps the order of the remaining items, relations to the item are removed too
+    pub fn remove(&mut self, id: u32) -> Option<base::Item> {
+        let index = self.index_of(id)?;
+        let related: Vec<_> = self.list[index].relations.iter().map(|(_, target)| *target).collect();
+        for target in related {
+            self.unlink(id, target);
+        }
+        let item = self.take(index);
+        self.changes.push(Change::Delete { index, line: item.to_line(), leading: item.leading.clone() });
+        Some(item)
+    }
+
+    // lines in front of the item stay in the file in front of the next one
+    fn take(&mut self, index: usize) -> base::Item {
+        let item = self.list.remove(index);
+        let leading = item.leading.iter().cloned();
+        match self.list.get_mut(index) {
+            Some(next) => next.leading.splice(0..0, leading),
+            None => self.trailing.splice(0..0, leading),
+        };
+        item
+    }
+
+    // inverse of `take`, the lines are moved back only when they are still in front of the next item
+    fn put(&mut self, index: usize, mut item: base::Item, leading: &[String]) {
+        let next = match self.list.get_mut(index) {
+            Some(next) => &mut next.leading,
+            None => &mut self.trailing,
+        };
+        if !leading.is_empty() && next.starts_with(leading) {
+            next.drain(..leading.len());
+            item.leading = leading.to_vec();
+        }
+        self.insert(index, item);
+    }
+
+    // unchanged items and other lines are written back as they were read
+    pub fn write_to(&self, output: &mut dyn Write) -> Result<(), io::Error> {
+        fn push_line(result: &mut String, line: &str) {
+            if !result.is_empty() && !result.ends_with('\n') {
+                result.push('\n');
+            }
+            result.push_str(line);
+        }
+        // the header is written by every save, so older lists are upgraded
+        let mut result = format!("{}\n{}\n", base::header(), base::next_id_header(self.next_id));
+        for item in &(self.list) {
+            for line in &item.leading {
+                push_line(&mut result, line);
+            }
+            match &item.source {
+                Some(source) if !item.is_changed() => push_line(&mut result, source),
+                Some(source) => {
+                    let line = item.to_line();
+                    let ending = &source[source.trim_end_matches(['\r', '\n']).len()..];
+                    push_line(&mut result, &line);
+                    result.push_str(ending);
+                }
+                None => {
+                    push_line(&mut result, &item.to_line());
+                    result.push('\n');
+                }
+            }
+        }
+        for line in &self.trailing {
+            push_line(&mut result, line);
+        }
         write!(output, "{}", result)
     }
 
-    pub fn get_item(&self, index: usize) -> Option<&base::Item> {
-        self.list.get(index)
+    pub fn get_item(&self, id: u32) -> Option<&base::Item> {
+        self.list.iter().find(|item| item.id == id)
     }
 
-    fn set_item<'a, F>(&'a mut self, index: usize, cond: F) -> Option<()>
+    // every changed field is recorded for the journal
+    fn set_item<F>(&mut self, id: u32, cond: F) -> Option<()>
     where
-        F: FnOnce(&'a mut base::Item) -> Option<()>,
+        F: FnOnce(&mut base::Item) -> Option<()>,
     {
-        self.list.get_mut(index).and_then(cond)
+        self.change_item(id, false, cond)
+    }
+
+    // changes of a started or stopped rewatch are marked as a pass boundary
+    fn change_item<F>(&mut self, id: u32, boundary: bool, cond: F) -> Option<()>
+    where
+        F: FnOnce(&mut base::Item) -> Option<()>,
+    {
+        let item = self.list.iter_mut().find(|item| item.id == id)?;
+        let before = item.fields();
+        cond(item)?;
+        for ((field, old), (_, new)) in before.into_iter().zip(item.fields()) {
+            if old == new {
+                continue;
+            }
+            let field = field.to_owned();
+            if boundary {
+                self.changes.push(Change::Pass { id, field, old, new });
+            } else {
+                self.changes.push(Change::Set { id, field, old, new });
+            }
+        }
+        Some(())
+    }
+
+    // replay a journal change, it is recorded again for the storage only, items are
+    // found by id and must still have the values the change was made from
+    pub fn apply(&mut self, change: &Change) -> Option<()> {
+        match change {
+            Change::Set { id, field, old, new } | Change::Pass { id, field, old, new } => {
+                let item = self.list.iter_mut().find(|item| item.id == *id)?;
+                if !item.fields().into_iter().any(|(f, value)| f == field && value == *old) {
+                    return None;
+                }
+                item.set_field(field, new)?;
+            }
+            Change::Insert { index, line, leading } => {
+                if *index > self.list.len() {
+                    return None;
+                }
+                let item = base::Item::parse(line).ok()?;
+                if item.id == 0 || self.get_item(item.id).is_some() {
+                    return None;
+                }
+                self.put(*index, item, leading);
+            }
+            Change::Delete { line, .. } => {
+                let expected = base::Item::parse(line).ok()?;
+                let index = self.index_of(expected.id).filter(|_| expected.id != 0)?;
+                if self.list[index].to_line() != expected.to_line() {
+                    return None;
+                }
+                let item = self.take(index);
+                // the storage needs the position the item was found at
+                self.changes.push(Change::Delete { index, line: line.clone(), leading: item.leading });
+                return Some(());
+            }
+        }
+        self.changes.push(change.clone());
+        Some(())
     }
 
-    pub fn set_maximum(&mut self, index: usize, value: base::SeriesCounter) -> Option<()> {
-        self.set_item(index, |f| {
+    // apply changes made to an older copy of the list, fails on the first change
+    // that conflicts with a newer value of the same field, returns new ids of added items
+    pub fn rebase(&mut self, changes: &[Change]) -> Result<HashMap<u32, u32>, Change> {
+        let mut ids = HashMap::new();
+        for change in changes {
+            match change {
+                Change::Set { id, field, old, new } | Change::Pass { id, field, old, new } => {
+                    let id = *ids.get(id).unwrap_or(id);
+                    let current =
+                        self.get_item(id).and_then(|item| item.fields().into_iter().find(|(f, _)| f == field));
+                    let boundary = matches!(change, Change::Pass { .. });
+                    match current {
+                        Some((_, value)) if value == *new => {}
+                        Some((_, value)) if value == *old => {
+                            self.change_item(id, boundary, |f| f.set_field(field, new))
+                                .ok_or_else(|| change.clone())?;
+                        }
+                        _ => return Err(change.clone()),
+                    }
+                }
+                Change::Insert { index, line, leading } => {
+                    let mut item = base::Item::parse(line).map_err(|_| change.clone())?;
+                    let old_id = item.id;
+                    let index = cmp::min(*index, self.list.len());
+                    if self.get_item(item.id).is_some() {
+                        item.id = self.next_id;
+                        ids.insert(old_id, item.id);
+                    }
+                    self.changes.push(Change::Insert { index, line: item.to_line(), leading: leading.clone() });
+                    self.put(index, item, leading);
+                }
+                Change::Delete { line, .. } => {
+                    let item = base::Item::parse(line).map_err(|_| change.clone())?;
+                    let id = *ids.get(&item.id).unwrap_or(&item.id);
+                    // already deleted by the other process
+                    self.remove(id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    pub fn update(&mut self, id: u32, from: &base::Item) -> Option<()> {
+        self.set_item(id, |f| {
+            for (field, value) in from.fields() {
+                f.set_field(field, &value)?;
+            }
+            Some(())
+        })
+    }
+
+    pub fn set_maximum(&mut self, id: u32, value: base::SeriesCounter) -> Option<()> {
+        self.set_item(id, |f| {
             f.maximum = value;
-            if let base::SeriesCounter::Value(max) = f.maximum {
+            if f.rewatching {
+                AnimeBase::update_progress_status(f);
+            } else if let base::SeriesCounter::Value(max) = f.maximum {
+                let status = f.status;
                 if f.progress >= max {
                     f.status = base::Status::Complete;
                 } else {
                     f.status = base::Status::Watch;
                 }
+                AnimeBase::update_dates(f, status);
             }
             Some(())
         })
     }
 
+    // dates are set on the first transition into watch or complete and kept after that
+    fn update_dates(f: &'_ mut base::Item, previous: base::Status) {
+        if f.status == previous {
+            return;
+        }
+        let today = Local::now().date_naive();
+        match f.status {
+            base::Status::Watch => {
+                f.started.get_or_insert(today);
+            }
+            base::Status::Complete => {
+                f.finished.get_or_insert(today);
+            }
+            _ => {}
+        }
+    }
+
+    // a rewatch keeps the item completed and only counts the pass when it reaches the end
     fn update_progress_status(f: &'_ mut base::Item) {
-        if let base::SeriesCounter::Value(max) = f.maximum {
+        if f.rewatching {
+            if let base::SeriesCounter::Value(max) = f.maximum {
+                if f.progress >= max {
+                    f.progress = max;
+                    f.rewatching = false;
+                    f.rewatches = f.rewatches.saturating_add(1);
+                }
+            }
+        } else if let base::SeriesCounter::Value(max) = f.maximum {
+            let status = f.status;
             if f.progress >= max {
                 f.progress = max;
                 f.status = base::Status::Complete;
             } else {
                 f.status = base::Status::Watch;
             }
+            AnimeBase::update_dates(f, status);
         }
     }
 
-    pub fn set_progress(&mut self, index: usize, value: u16) -> Option<()> {
-        self.set_item(index, |f| {
+    pub fn set_progress(&mut self, id: u32, value: u16) -> Option<()> {
+        self.set_item(id, |f| {
             f.progress = value;
             AnimeBase::update_progress_status(f);
             Some(())
         })
     }
 
-    pub fn set_status(&mut self, index: usize, status: base::Status) -> Option<()> {
-        self.set_item(index, |f| {
+    pub fn set_status(&mut self, id: u32, status: base::Status) -> Option<()> {
+        self.set_item(id, |f| {
+            let previous = f.status;
             f.status = status;
+            f.rewatching = false;
+            AnimeBase::update_dates(f, previous);
             if status == base::Status::Complete {
                 if let base::SeriesCounter::Value(max) = f.maximum {
                     f.progress = max
@@ -142,30 +785,376 @@ impl AnimeBase {
         })
     }
 
-    pub fn set_rate(&mut self, index: usize, value: u8) -> Option<()> {
-        self.set_item(index, |f| {
+    // only completed items can be rewatched
+    pub fn start_rewatch(&mut self, id: u32) -> Option<()> {
+        self.change_item(id, true, |f| {
+            if f.status != base::Status::Complete || f.rewatching {
+                return None;
+            }
+            f.rewatching = true;
+            f.progress = 0;
+            Some(())
+        })
+    }
+
+    pub fn stop_rewatch(&mut self, id: u32) -> Option<()> {
+        self.change_item(id, true, |f| {
+            if !f.rewatching {
+                return None;
+            }
+            f.rewatching = false;
+            if let base::SeriesCounter::Value(max) = f.maximum {
+                f.progress = max;
+            }
+            Some(())
+        })
+    }
+
+    pub fn set_rewatches(&mut self, id: u32, value: u16) -> Option<()> {
+        self.set_item(id, |f| {
+            f.rewatches = value;
+            Some(())
+        })
+    }
+
+    pub fn set_kind(&mut self, id: u32, kind: base::MediaType) -> Option<()> {
+        self.set_item(id, |f| {
+            f.kind = kind;
+            Some(())
+        })
+    }
+
+    fn clamp_volume(f: &'_ mut base::Item) {
+        if let base::SeriesCounter::Value(max) = f.volumes {
+            f.volume = cmp::min(f.volume, max);
+        }
+    }
+
+    pub fn set_volume(&mut self, id: u32, value: u16) -> Option<()> {
+        self.set_item(id, |f| {
+            f.volume = value;
+            AnimeBase::clamp_volume(f);
+            Some(())
+        })
+    }
+
+    pub fn set_volumes(&mut self, id: u32, value: base::SeriesCounter) -> Option<()> {
+        self.set_item(id, |f| {
+            f.volumes = value;
+            AnimeBase::clamp_volume(f);
+            Some(())
+        })
+    }
+
+    pub fn volume_increment_by(&mut self, id: u32, value: u16) -> Option<()> {
+        self.set_item(id, |f| {
+            f.volume = f.volume.saturating_add(value);
+            AnimeBase::clamp_volume(f);
+            Some(())
+        })
+    }
+
+    pub fn volume_decrement_by(&mut self, id: u32, value: u16) -> Option<()> {
+        self.set_item(id, |f| {
+            f.volume = f.volume.saturating_sub(value);
+            Some(())
+        })
+    }
+
+    pub fn set_rate(&mut self, id: u32, value: u8) -> Option<()> {
+        self.set_item(id, |f| {
             f.rate = value;
             Some(())
         })
     }
 
-    pub fn set_name(&mut self, index: usize, name: &str) -> Option<()> {
-        self.set_item(index, |f| {
+    pub fn set_started(&mut self, id: u32, date: Option<NaiveDate>) -> Option<()> {
+        self.set_item(id, |f| {
+            f.started = date;
+            Some(())
+        })
+    }
+
+    pub fn set_finished(&mut self, id: u32, date: Option<NaiveDate>) -> Option<()> {
+        self.set_item(id, |f| {
+            f.finished = date;
+            Some(())
+        })
+    }
+
+    pub fn set_priority(&mut self, id: u32, priority: u8) -> Option<()> {
+        self.set_item(id, |f| {
+            f.priority = priority;
+            Some(())
+        })
+    }
+
+    // `None` returns to the duration of the media type
+    pub fn set_duration(&mut self, id: u32, duration: Option<u16>) -> Option<()> {
+        self.set_item(id, |f| {
+            f.duration = duration;
+            Some(())
+        })
+    }
+
+    pub fn watch_time(&self, ids: &[u32]) -> WatchTime {
+        fn add<T: PartialEq>(list: &mut Vec<(T, u32)>, key: T, minutes: u32) {
+            match list.iter_mut().find(|(other, _)| *other == key) {
+                Some((_, total)) => *total += minutes,
+                None => list.push((key, minutes)),
+            }
+        }
+        let mut result =
+            WatchTime { total: 0, by_status: Vec::new(), by_tag: Vec::new(), remaining: Vec::new(), unknown: 0 };
+        for item in ids.iter().filter_map(|id| self.get_item(*id)) {
+            let minutes = item.watched_minutes();
+            result.total += minutes;
+            add(&mut result.by_status, item.status, minutes);
+            for tag in &item.tags {
+                add(&mut result.by_tag, tag.to_lowercase(), minutes);
+            }
+            if matches!(item.status, base::Status::Watch | base::Status::Plan) {
+                match item.remaining_minutes() {
+                    Some(minutes) => add(&mut result.remaining, item.status, minutes),
+                    None => result.unknown += 1,
+                }
+            }
+        }
+        result.by_tag.sort_by_key(|(_, minutes)| cmp::Reverse(*minutes));
+        result
+    }
+
+    // plan items by priority, ids grow so older items go first
+    pub fn queue(&self) -> Vec<&base::Item> {
+        let mut result: Vec<_> = self.list.iter().filter(|item| item.status == base::Status::Plan).collect();
+        result.sort_by_key(|item| (cmp::Reverse(item.priority), item.id));
+        result
+    }
+
+    // watched items with episodes left and plan items of at least `priority`,
+    // watched ones go first
+    pub fn next_candidates(&self, priority: u8, date: NaiveDate) -> Vec<&base::Item> {
+        let mut result: Vec<_> = self
+            .list
+            .iter()
+            .filter(|item| match item.status {
+                base::Status::Watch => item.has_remaining(date),
+                base::Status::Plan => item.priority >= priority,
+                _ => false,
+            })
+            .collect();
+        result.sort_by_key(|item| (item.status != base::Status::Watch, cmp::Reverse(item.priority), item.id));
+        result
+    }
+
+    // the first candidate or a random one, an item of priority n is n + 1 times more likely than one without
+    pub fn pick_next(&self, priority: u8, date: NaiveDate, random: bool) -> Option<u32> {
+        let candidates = self.next_candidates(priority, date);
+        if !random {
+            return candidates.first().map(|item| item.id);
+        }
+        let total: u32 = candidates.iter().map(|item| item.priority as u32 + 1).sum();
+        let mut point = fastrand::u32(0..cmp::max(total, 1));
+        for item in candidates {
+            let weight = item.priority as u32 + 1;
+            if point < weight {
+                return Some(item.id);
+            }
+            point -= weight;
+        }
+        None
+    }
+
+    // a new start keeps the interval and the total of the schedule
+    pub fn set_airing(&mut self, id: u32, date: Option<NaiveDate>) -> Option<()> {
+        self.set_item(id, |f| {
+            f.airing = date.map(|start| match f.airing {
+                Some(airing) => base::Airing { start, ..airing },
+                None => base::Airing::new(start),
+            });
+            Some(())
+        })
+    }
+
+    pub fn set_airing_interval(&mut self, id: u32, interval: u16) -> Option<()> {
+        self.set_item(id, |f| {
+            f.airing.as_mut()?.interval = interval;
+            Some(())
+        })
+    }
+
+    pub fn set_airing_total(&mut self, id: u32, total: base::SeriesCounter) -> Option<()> {
+        self.set_item(id, |f| {
+            f.airing.as_mut()?.total = total;
+            Some(())
+        })
+    }
+
+    pub fn add_tags(&mut self, id: u32, tags: &[String]) -> Option<()> {
+        self.set_item(id, |f| {
+            for tag in tags {
+                if !f.has_tag(tag) {
+                    f.tags.push(tag.clone());
+                }
+            }
+            Some(())
+        })
+    }
+
+    pub fn remove_tags(&mut self, id: u32, tags: &[String]) -> Option<()> {
+        self.set_item(id, |f| {
+            let tags: Vec<_> = tags.iter().map(|tag| tag.to_lowercase()).collect();
+            f.tags.retain(|tag| !tags.contains(&tag.to_lowercase()));
+            Some(())
+        })
+    }
+
+    pub fn set_note(&mut self, id: u32, note: &str) -> Option<()> {
+        self.set_item(id, |f| {
+            f.note = note.to_owned();
+            Some(())
+        })
+    }
+
+    // renaming to an alias swaps it with the name when the old name is kept
+    pub fn set_name(&mut self, id: u32, name: &str, keep: bool) -> Option<()> {
+        self.set_item(id, |f| {
+            if keep && f.name != name && !f.aliases.contains(&f.name) {
+                f.aliases.push(f.name.clone());
+            }
+            f.aliases.retain(|alias| alias != name);
             f.name = name.to_string();
             Some(())
         })
     }
 
-    pub fn progress_increment_by(&mut self, index: usize, value: u16) -> Option<()> {
-        self.set_item(index, |f| {
+    pub fn set_custom(&mut self, id: u32, key: &str, value: &str) -> Option<()> {
+        self.set_item(id, |f| {
+            f.set_custom(key, value);
+            Some(())
+        })
+    }
+
+    // set once from the config before items are shown
+    pub fn show_custom(keys: Vec<String>) {
+        let _ = SHOWN_CUSTOM.set(keys);
+    }
+
+    pub fn add_alias(&mut self, id: u32, alias: &str) -> Option<()> {
+        self.set_item(id, |f| {
+            if !f.has_title(alias) {
+                f.aliases.push(alias.to_owned());
+            }
+            Some(())
+        })
+    }
+
+    pub fn remove_alias(&mut self, id: u32, alias: &str) -> Option<()> {
+        self.set_item(id, |f| {
+            f.aliases.retain(|f| f != alias);
+            Some(())
+        })
+    }
+
+    // links both items, the other one gets the inverse relation
+    pub fn link(&mut self, id: u32, kind: base::Relation, target: u32) -> Option<()> {
+        if id == target || self.get_item(target).is_none() {
+            return None;
+        }
+        self.unlink(id, target);
+        self.set_item(id, |f| {
+            f.relations.push((kind, target));
+            Some(())
+        })?;
+        self.set_item(target, |f| {
+            f.relations.push((kind.inverse(), id));
+            Some(())
+        })
+    }
+
+    pub fn unlink(&mut self, id: u32, target: u32) {
+        self.set_item(id, |f| {
+            f.relations.retain(|(_, other)| *other != target);
+            Some(())
+        });
+        self.set_item(target, |f| {
+            f.relations.retain(|(_, other)| *other != id);
+            Some(())
+        });
+    }
+
+    // items connected to `id` by any relation, including the item itself
+    fn franchise(&self, id: u32) -> Vec<u32> {
+        let mut result = vec![id];
+        let mut index = 0;
+        while let Some(current) = result.get(index) {
+            let item = self.get_item(*current);
+            for (_, other) in item.iter().flat_map(|item| &item.relations) {
+                if !result.contains(other) && self.get_item(*other).is_some() {
+                    result.push(*other);
+                }
+            }
+            index += 1;
+        }
+        result
+    }
+
+    // from the first entries without prequel, each one followed by its side stories and then its sequels,
+    // entries only in the same franchise go last
+    pub fn watch_order(&self, id: u32) -> Vec<u32> {
+        fn visit(base: &AnimeBase, id: u32, result: &mut Vec<u32>) {
+            if result.contains(&id) {
+                return;
+            }
+            result.push(id);
+            let relations = base.get_item(id).map(|item| item.relations.clone()).unwrap_or_default();
+            for kind in [base::Relation::SideStory, base::Relation::Sequel] {
+                let mut next: Vec<_> = relations.iter().filter(|(k, _)| *k == kind).map(|(_, id)| *id).collect();
+                next.sort_unstable();
+                for id in next {
+                    if base.get_item(id).is_some() {
+                        visit(base, id, result);
+                    }
+                }
+            }
+        }
+
+        let mut franchise = self.franchise(id);
+        franchise.sort_unstable();
+        let mut result = Vec::new();
+        for id in &franchise {
+            let is_first = self.get_item(*id).is_some_and(|item| {
+                !item.relations.iter().any(|(kind, other)| {
+                    matches!(kind, base::Relation::Prequel | base::Relation::MainStory) && franchise.contains(other)
+                })
+            });
+            if is_first {
+                visit(self, *id, &mut result);
+            }
+        }
+        for id in franchise {
+            visit(self, id, &mut result);
+        }
+        result
+    }
+
+    // first entry in the watch order that is neither completed nor dropped
+    pub fn next_in_franchise(&self, id: u32) -> Option<u32> {
+        self.watch_order(id).into_iter().find(|id| {
+            self.get_item(*id).is_some_and(|item| !matches!(item.status, base::Status::Complete | base::Status::Drop))
+        })
+    }
+
+    pub fn progress_increment_by(&mut self, id: u32, value: u16) -> Option<()> {
+        self.set_item(id, |f| {
             f.progress = f.progress.saturating_add(value);
             AnimeBase::update_progress_status(f);
             Some(())
         })
     }
 
-    pub fn progress_decrement_by(&mut self, index: usize, value: u16) -> Option<()> {
-        self.set_item(index, |f| {
+    pub fn progress_decrement_by(&mut self, id: u32, value: u16) -> Option<()> {
+        self.set_item(id, |f| {
             f.progress = f.progress.saturating_sub(value);
             AnimeBase::update_progress_status(f);
             Some(())
@@ -190,6 +1179,12 @@ impl ExecCmd {
     pub fn get(cmd: &str, iter: &mut parser::Splitter) -> ExecCmd {
         let (cmd, other) = cmd.split_at(1);
         match cmd {
+            // increment volumes
+            "+" if other.starts_with('v') => match other[1..].parse() {
+                Ok(value) => ExecCmd::VolumeIncrement(value),
+                Err(_) if other.len() == 1 => ExecCmd::VolumeIncrement(1),
+                Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+            },
             // Increment series progress
             "+" => {
                 if !other.is_empty() {
@@ -203,6 +1198,12 @@ impl ExecCmd {
                     ExecCmd::Increment(1)
                 }
             }
+            // decrement volumes
+            "-" if other.starts_with('v') => match other[1..].parse() {
+                Ok(value) => ExecCmd::VolumeDecrement(value),
+                Err(_) if other.len() == 1 => ExecCmd::VolumeDecrement(1),
+                Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+            },
             // decrement series progress
             "-" => {
                 if !other.is_empty() {
@@ -218,34 +1219,93 @@ impl ExecCmd {
             }
             // append new item
             "a" => match iter.next() {
-                Some(new_name) => ExecCmd::Append(new_name.to_owned()),
+                Some(new_name) => ExecCmd::Append(parser::unescape(new_name)),
                 None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
             },
+            // restore from backup
+            "b" => {
+                if !other.is_empty() {
+                    match other.parse() {
+                        Ok(value) => ExecCmd::Restore(value),
+                        Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                    }
+                } else {
+                    ExecCmd::Restore(1)
+                }
+            }
             // delete
             "d" => ExecCmd::Delete,
+            // rewatch
+            "r" => match other {
+                "" => ExecCmd::Rewatch,
+                // stop
+                "s" => ExecCmd::StopRewatch,
+                // set count
+                _ if other.starts_with('n') => match other[1..].parse() {
+                    Ok(value) => ExecCmd::Rewatches(value),
+                    Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                },
+                _ => ExecCmd::Error(ErrorStatus::UnknownCommand),
+            },
             // info
             "i" => ExecCmd::Info,
+            // aired episodes to watch
+            "v" if other.is_empty() => ExecCmd::Available,
+            // plan queue
+            "q" if other.is_empty() => ExecCmd::Queue,
+            // watch time
+            "m" if other.is_empty() => ExecCmd::WatchTime,
+            // what to watch next
+            "n" => match other {
+                "" => ExecCmd::Next(false),
+                // weighted random
+                "r" => ExecCmd::Next(true),
+                _ => ExecCmd::Error(ErrorStatus::UnknownCommand),
+            },
             // find
             "f" => {
-                if other.len() > 1 {
+                if other == "t" {
+                    // by tag
+                    match iter.next() {
+                        Some(tag) => ExecCmd::FindParam(ParamType::tag(tag)),
+                        None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                    }
+                } else if other == "u" {
+                    // by custom field
+                    match iter.next() {
+                        Some(field) => ExecCmd::FindParam(ParamType::custom(&parser::unescape(field))),
+                        None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                    }
+                } else if other.len() > 1 {
                     let (other, param) = other.split_at(1);
                     match other {
+                        // by id
+                        "i" => ParamType::id(param).map_or_else(ExecCmd::Error, ExecCmd::FindParam),
                         // by status
-                        "s" => ExecCmd::FindParam(ParamType::Status(base::Status::from(param))),
-                        // by progress
-                        "p" => match param.parse() {
-                            Ok(value) => ExecCmd::FindParam(ParamType::Progress(value)),
-                            Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        "s" => match base::Status::from(param) {
+                            base::Status::Error => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                            status => ExecCmd::FindParam(ParamType::Status(status)),
                         },
-                        // by series maximum
-                        "m" => match param.parse() {
-                            Ok(value) => ExecCmd::FindParam(ParamType::Maximum(value)),
-                            Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        // by media type
+                        "k" => match base::MediaType::from(param) {
+                            base::MediaType::Error => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                            kind => ExecCmd::FindParam(ParamType::Kind(kind)),
                         },
+                        // by progress
+                        "p" => ParamType::progress(param).map_or_else(ExecCmd::Error, ExecCmd::FindParam),
+                        // by series maximum
+                        "m" => ParamType::maximum(param).map_or_else(ExecCmd::Error, ExecCmd::FindParam),
                         // by rate
-                        "r" => match param.parse() {
-                            Ok(value) => ExecCmd::FindParam(ParamType::Rate(value)),
-                            Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        "r" => ParamType::rate(param).map_or_else(ExecCmd::Error, ExecCmd::FindParam),
+                        // by start date
+                        "b" => match param.parse() {
+                            Ok(value) => ExecCmd::FindParam(ParamType::Started(value)),
+                            Err(err) => ExecCmd::Error(err),
+                        },
+                        // by finish date
+                        "f" => match param.parse() {
+                            Ok(value) => ExecCmd::FindParam(ParamType::Finished(value)),
+                            Err(err) => ExecCmd::Error(err),
                         },
                         _ => ExecCmd::Error(ErrorStatus::UnknownCommand),
                     }
@@ -268,8 +1328,24 @@ impl ExecCmd {
                             Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
                         },
                         // series name
-                        "n" => match iter.next() {
-                            Some(new_name) => ExecCmd::Rename(new_name.to_owned()),
+                        "n" if param.is_empty() || param == "a" => match iter.next() {
+                            Some(new_name) => ExecCmd::Rename(parser::unescape(new_name), param == "a"),
+                            None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                        },
+                        // add alias
+                        "a" => match iter.next() {
+                            Some(alias) => ExecCmd::AddAlias(parser::unescape(alias)),
+                            None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                        },
+                        // custom field
+                        "u" => match iter.next().map(|field| base::parse_custom(&parser::unescape(field))) {
+                            Some(Some((key, value))) => ExecCmd::Custom(key, value),
+                            Some(None) => ExecCmd::Error(ErrorStatus::CustomParseError),
+                            None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                        },
+                        // remove alias
+                        "d" => match iter.next() {
+                            Some(alias) => ExecCmd::RemoveAlias(parser::unescape(alias)),
                             None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
                         },
                         // series progress
@@ -278,12 +1354,164 @@ impl ExecCmd {
                             Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
                         },
                         // series rate
-                        "r" => match param.parse() {
-                            Ok(value) => ExecCmd::Rate(value),
-                            Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        "r" => match base::Scoring::current().parse(param) {
+                            Some(value) => ExecCmd::Rate(value),
+                            None => ExecCmd::Error(ErrorStatus::ScoreParseError),
                         },
                         // series status
-                        "s" => ExecCmd::Status(base::Status::from(param)),
+                        "s" => match base::Status::from(param) {
+                            base::Status::Error => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                            status => ExecCmd::Status(status),
+                        },
+                        // episode duration
+                        "l" if param == "?" => ExecCmd::Duration(None),
+                        "l" => match param.parse() {
+                            Ok(value) if value != 0 => ExecCmd::Duration(Some(value)),
+                            _ => ExecCmd::Error(ErrorStatus::IntParseError),
+                        },
+                        // priority
+                        "q" => match param.parse() {
+                            Ok(value) if value <= base::MAX_PRIORITY => ExecCmd::Priority(value),
+                            _ => ExecCmd::Error(ErrorStatus::IntParseError),
+                        },
+                        // media type
+                        "k" => match base::MediaType::from(param) {
+                            base::MediaType::Error => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                            kind => ExecCmd::Kind(kind),
+                        },
+                        // total volumes
+                        "v" if param.starts_with('m') => match param[1..].parse() {
+                            Ok(value) => ExecCmd::VolumeMaximum(value),
+                            Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        },
+                        // read volumes
+                        "v" => match param.parse() {
+                            Ok(value) => ExecCmd::Volume(value),
+                            Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        },
+                        // note
+                        "c" => match iter.next() {
+                            Some(note) => ExecCmd::Note(parser::unescape(note)),
+                            None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                        },
+                        // note in $EDITOR
+                        "e" => ExecCmd::EditNote,
+                        // start date
+                        "b" => match parse_date(param) {
+                            Ok(date) => ExecCmd::Started(date),
+                            Err(err) => ExecCmd::Error(err),
+                        },
+                        // finish date
+                        "f" => match parse_date(param) {
+                            Ok(date) => ExecCmd::Finished(date),
+                            Err(err) => ExecCmd::Error(err),
+                        },
+                        // days between episodes
+                        "y" if param.starts_with('i') => match param[1..].parse() {
+                            Ok(value) if value != 0 => ExecCmd::AiringInterval(value),
+                            _ => ExecCmd::Error(ErrorStatus::IntParseError),
+                        },
+                        // expected episodes
+                        "y" if param.starts_with('t') => match param[1..].parse() {
+                            Ok(value) => ExecCmd::AiringTotal(value),
+                            Err(err) => ExecCmd::Error(err),
+                        },
+                        // airing start
+                        "y" => match parse_date(param) {
+                            Ok(date) => ExecCmd::Airing(date),
+                            Err(err) => ExecCmd::Error(err),
+                        },
+                        _ => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                    }
+                } else {
+                    ExecCmd::Error(ErrorStatus::UnknownCommand)
+                }
+            }
+            // tags
+            "t" => match other {
+                "a" | "d" => match iter
+                    .next()
+                    .map(|tags| base::parse_tags(&parser::unescape(tags)))
+                    .filter(|tags| !tags.is_empty())
+                {
+                    // add
+                    Some(tags) if other == "a" => ExecCmd::AddTags(tags),
+                    // remove
+                    Some(tags) => ExecCmd::RemoveTags(tags),
+                    None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                },
+                _ => ExecCmd::Error(ErrorStatus::UnknownCommand),
+            },
+            // relations
+            "l" => {
+                if other == "n" {
+                    ExecCmd::NextInFranchise
+                } else if other.len() > 1 {
+                    let (other, param) = other.split_at(1);
+                    match (other, param.parse()) {
+                        ("u", Ok(id)) => ExecCmd::Unlink(id),
+                        (_, Err(_)) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        (kind, Ok(id)) => match base::Relation::from(kind) {
+                            Some(kind) => ExecCmd::Link(kind, id),
+                            None => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                        },
+                    }
+                } else {
+                    ExecCmd::Error(ErrorStatus::UnknownCommand)
+                }
+            }
+            // journal
+            "j" => {
+                if !other.is_empty() {
+                    let (other, param) = other.split_at(1);
+                    let count = if param.is_empty() { Ok(1) } else { param.parse() };
+                    match (other, count) {
+                        ("u", Ok(value)) => ExecCmd::Undo(value),
+                        ("r", Ok(value)) => ExecCmd::Redo(value),
+                        ("u", Err(_)) | ("r", Err(_)) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        _ => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                    }
+                } else {
+                    ExecCmd::Journal
+                }
+            }
+            // episode history
+            "h" => {
+                if !other.is_empty() {
+                    let (other, param) = other.split_at(1);
+                    let count = if param.is_empty() { Ok(10) } else { param.parse() };
+                    match (other, count) {
+                        ("r", Ok(value)) => ExecCmd::Recent(value),
+                        ("r", Err(_)) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        _ => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                    }
+                } else {
+                    ExecCmd::History
+                }
+            }
+            // export
+            "e" => match exchange::Format::from(other) {
+                Some(format) => match iter.next() {
+                    Some(path) => ExecCmd::Export(format, path.to_owned()),
+                    None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                },
+                None => ExecCmd::Error(ErrorStatus::UnknownCommand),
+            },
+            // import
+            "o" => {
+                if other.len() > 1 {
+                    let (format, mode) = other.split_at(1);
+                    let mode = match mode {
+                        "m" => Some(ImportMode::Merge),
+                        "r" => Some(ImportMode::Replace),
+                        "d" => Some(ImportMode::DryRun),
+                        _ => None,
+                    };
+                    match (exchange::Format::from(format), mode) {
+                        (Some(format), Some(mode)) => match iter.next() {
+                            Some(path) => ExecCmd::Import(format, mode, path.to_owned()),
+                            None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                        },
                         _ => ExecCmd::Error(ErrorStatus::UnknownCommand),
                     }
                 } else {
@@ -294,25 +1522,48 @@ impl ExecCmd {
             "w" => ExecCmd::Write,
             // filter command
             "x" => {
-                if other.len() > 1 {
+                if other == "t" {
+                    // tag
+                    match iter.next() {
+                        Some(tag) => ExecCmd::FilterParam(ParamType::tag(tag)),
+                        None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                    }
+                } else if other == "u" {
+                    // custom field
+                    match iter.next() {
+                        Some(field) => ExecCmd::FilterParam(ParamType::custom(&parser::unescape(field))),
+                        None => ExecCmd::Error(ErrorStatus::EmptyFieldError),
+                    }
+                } else if other.len() > 1 {
                     let (other, param) = other.split_at(1);
                     match other {
+                        // id
+                        "i" => ParamType::id(param).map_or_else(ExecCmd::Error, ExecCmd::FilterParam),
                         // status
-                        "s" => ExecCmd::FilterParam(ParamType::Status(base::Status::from(param))),
-                        // progress
-                        "p" => match param.parse() {
-                            Ok(value) => ExecCmd::FilterParam(ParamType::Progress(value)),
-                            Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        "s" => match base::Status::from(param) {
+                            base::Status::Error => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                            status => ExecCmd::FilterParam(ParamType::Status(status)),
                         },
-                        // series max
-                        "m" => match param.parse() {
-                            Ok(value) => ExecCmd::FilterParam(ParamType::Maximum(value)),
-                            Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        // media type
+                        "k" => match base::MediaType::from(param) {
+                            base::MediaType::Error => ExecCmd::Error(ErrorStatus::UnknownCommand),
+                            kind => ExecCmd::FilterParam(ParamType::Kind(kind)),
                         },
+                        // progress
+                        "p" => ParamType::progress(param).map_or_else(ExecCmd::Error, ExecCmd::FilterParam),
+                        // series max
+                        "m" => ParamType::maximum(param).map_or_else(ExecCmd::Error, ExecCmd::FilterParam),
                         // rate
-                        "r" => match param.parse() {
-                            Ok(value) => ExecCmd::FilterParam(ParamType::Rate(value)),
-                            Err(_) => ExecCmd::Error(ErrorStatus::IntParseError),
+                        "r" => ParamType::rate(param).map_or_else(ExecCmd::Error, ExecCmd::FilterParam),
+                        // start date
+                        "b" => match param.parse() {
+                            Ok(value) => ExecCmd::FilterParam(ParamType::Started(value)),
+                            Err(err) => ExecCmd::Error(err),
+                        },
+                        // finish date
+                        "f" => match param.parse() {
+                            Ok(value) => ExecCmd::FilterParam(ParamType::Finished(value)),
+                            Err(err) => ExecCmd::Error(err),
                         },
                         _ => ExecCmd::Error(ErrorStatus::UnknownCommand),
                     }
@@ -324,3 +1575,121 @@ impl ExecCmd {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(base: &AnimeBase) -> String {
+        let mut output = Vec::new();
+        base.write_to(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    // comments, blank lines, spacing and line endings of unchanged items are kept
+    const COMMENTED: &str = "#! watch-list format 14\n\
+        #! next id 4\n\
+        # seasonal\n\
+        \n\
+        \"Berserk\"  hold progress 5/25 score 80 id 1\r\n\
+        \"Say \\\"hi\\\" \\\\o/\" watch progress 1/12 score 0 note \"a\\nb\" id 2\n\
+        # finished\n\
+        \"Bakemonogatari\" complete progress 15/15 score 100 id 3\n\
+        \n\
+        # end";
+
+    #[test]
+    fn write_keeps_commented_list() {
+        let (base, errors) = AnimeBase::parse(COMMENTED);
+        assert!(errors.is_empty());
+        assert_eq!(base.list.len(), 3);
+        assert!(base.list.iter().all(|item| !item.is_changed()));
+        assert_eq!(base.list[1].name, "Say \"hi\" \\o/");
+        assert_eq!(base.list[1].note, "a\nb");
+        assert_eq!(write(&base), COMMENTED);
+    }
+
+    #[test]
+    fn write_rewrites_changed_item_only() {
+        let (mut base, _) = AnimeBase::parse(COMMENTED);
+        base.list[0].progress = 6;
+        assert!(base.list[0].is_changed());
+        assert!(!base.list[1].is_changed());
+        let expected = COMMENTED.replace(
+            "\"Berserk\"  hold progress 5/25 score 80 id 1\r\n",
+            "\"Berserk\" hold progress 6/25 score 80 id 1\r\n",
+        );
+        assert_eq!(write(&base), expected);
+    }
+
+    #[test]
+    fn write_upgrades_header() {
+        let (base, errors) = AnimeBase::parse("# old\n\"Berserk\" hold progress 5/25 score 8\n");
+        assert!(errors.is_empty());
+        assert_eq!(
+            write(&base),
+            "#! watch-list format 14\n#! next id 2\n# old\n\"Berserk\" hold progress 5/25 score 80 id 1\n"
+        );
+    }
+
+    fn rate(s: &str) -> Option<Compare<u8>> {
+        Compare::parse(s, |s| base::Scoring::Ten.range(s))
+    }
+
+    #[test]
+    fn compare_parses_operators() {
+        assert_eq!(Compare::parse("12", number), Some(Compare::Within(12u16, 12)));
+        assert_eq!(Compare::parse("<12", number), Some(Compare::Below(12u16)));
+        assert_eq!(Compare::parse("<=12", number), Some(Compare::AtMost(12u16)));
+        assert_eq!(Compare::parse("!=12", number), Some(Compare::Outside(12u16, 12)));
+        assert_eq!(Compare::parse(">12", number), Some(Compare::Above(12u16)));
+        assert_eq!(Compare::parse(">=12", number), Some(Compare::AtLeast(12u16)));
+        assert_eq!(Compare::parse("3..12", number), Some(Compare::Within(3u16, 12)));
+        assert_eq!(Compare::parse("..12", number), Some(Compare::AtMost(12u16)));
+        assert_eq!(Compare::parse("3..", number), Some(Compare::AtLeast(3u16)));
+        assert_eq!(Compare::<u16>::parse("..", number), None);
+        assert_eq!(Compare::<u16>::parse("<x", number), None);
+        assert_eq!(Compare::<u16>::parse("3..x", number), None);
+    }
+
+    // a 10-point score stands for the stored scores rounded to it
+    #[test]
+    fn compare_rounds_ten_point_scores() {
+        assert_eq!(rate("7"), Some(Compare::Within(65, 74)));
+        assert_eq!(rate("<7"), Some(Compare::Below(65)));
+        assert_eq!(rate("<=7"), Some(Compare::AtMost(74)));
+        assert_eq!(rate("!=7"), Some(Compare::Outside(65, 74)));
+        assert_eq!(rate(">7"), Some(Compare::Above(74)));
+        assert_eq!(rate(">=7"), Some(Compare::AtLeast(65)));
+        assert_eq!(rate("7..9"), Some(Compare::Within(65, 94)));
+        assert_eq!(rate("..7"), Some(Compare::AtMost(74)));
+        assert_eq!(rate("7.."), Some(Compare::AtLeast(65)));
+        assert_eq!(rate("0"), Some(Compare::Within(0, 4)));
+        assert_eq!(rate("10"), Some(Compare::Within(95, 100)));
+        assert_eq!(rate("11"), None);
+        assert_eq!(rate("<=11"), None);
+    }
+
+    #[test]
+    fn compare_matches_rounding_boundaries() {
+        let within = rate("7").unwrap();
+        assert!(!within.matches(64) && within.matches(65) && within.matches(74) && !within.matches(75));
+        let outside = rate("!=7").unwrap();
+        assert!(outside.matches(64) && !outside.matches(65) && !outside.matches(74) && outside.matches(75));
+        let below = rate("<7").unwrap();
+        assert!(below.matches(64) && !below.matches(65));
+        let at_most = rate("<=7").unwrap();
+        assert!(at_most.matches(74) && !at_most.matches(75));
+        let range = rate("7..9").unwrap();
+        assert!(!range.matches(64) && range.matches(65) && range.matches(94) && !range.matches(95));
+    }
+
+    #[test]
+    fn rate_uses_configured_scoring() {
+        // the scoring is not initialized in tests, so it is the 10-point one
+        assert!(matches!(ParamType::rate("<=7"), Ok(ParamType::Rate(Compare::AtMost(74)))));
+        assert!(matches!(ParamType::rate("7..11"), Err(ErrorStatus::ScoreParseError)));
+        assert!(matches!(ParamType::progress("!=3"), Ok(ParamType::Progress(Compare::Outside(3, 3)))));
+        assert!(matches!(ParamType::id("x"), Err(ErrorStatus::IntParseError)));
+    }
+}
@@ -0,0 +1,155 @@
+use crate::extra::AnimeBase;
+use crate::journal::Change;
+
+use chrono::{Local, TimeZone};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+// one episode of an item marked as watched or unwatched by a saved command
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub time: i64,
+    pub id: u32,
+    pub episode: u16,
+    pub watched: bool,
+    // watch pass of the item, rewatches start from 2
+    pub pass: u16,
+    pub command: String,
+}
+
+pub struct History {
+    filename: String,
+    pub events: Vec<Event>,
+}
+
+impl Event {
+    fn parse(line: &str) -> Option<Event> {
+        let mut raw = line.splitn(4, ' ');
+        let time = raw.next()?.parse().ok()?;
+        let id = raw.next()?.parse().ok()?;
+        let episode = raw.next()?;
+        let command = raw.next()?.to_owned();
+        let (episode, pass) = match episode.split_once('@') {
+            Some((episode, pass)) => (episode, pass.parse().ok()?),
+            None => (episode, 1),
+        };
+        let (watched, episode) = match episode.split_at_checked(1)? {
+            ("+", episode) => (true, episode.parse().ok()?),
+            ("-", episode) => (false, episode.parse().ok()?),
+            _ => return None,
+        };
+        Some(Event { time, id, episode, watched, pass, command })
+    }
+
+    fn to_line(&self) -> String {
+        let sign = if self.watched { '+' } else { '-' };
+        let pass = if self.pass > 1 { format!("@{}", self.pass) } else { String::new() };
+        format!("{} {} {}{}{} {}", self.time, self.id, sign, self.episode, pass, self.command)
+    }
+}
+
+impl History {
+    // history is kept next to the list file and is only appended to
+    pub fn open(list_filename: &str) -> Result<History, io::Error> {
+        let filename = format!("{}.history", list_filename);
+        let mut events = Vec::new();
+        if Path::new(&filename).exists() {
+            for (number, line) in fs::read_to_string(&filename)?.lines().enumerate() {
+                match Event::parse(line) {
+                    Some(event) => events.push(event),
+                    None => warn!("{}: invalid event at line {}", filename, number + 1),
+                }
+            }
+        }
+        Ok(History { filename, events })
+    }
+
+    // every episode passed by a progress change becomes an event, a progress reset at
+    // a pass boundary is not watched and is skipped, the pass of an episode is the one
+    // the item has right after the change, so the last episode belongs to a finished rewatch
+    pub fn record(&mut self, command: &str, changes: &[Change], base: &AnimeBase) -> Result<(), io::Error> {
+        let time = Local::now().timestamp();
+        let passes = History::passes(changes, base);
+        let mut events = Vec::new();
+        for (change, pass) in changes.iter().zip(passes) {
+            let (id, old, new) = match change {
+                Change::Set { id, field, old, new } if field == "progress" => (*id, old, new),
+                _ => continue,
+            };
+            let (old, new): (u16, u16) = match (old.parse(), new.parse()) {
+                (Ok(old), Ok(new)) => (old, new),
+                _ => continue,
+            };
+            let event = |episode, watched| Event { time, id, episode, watched, pass, command: command.to_owned() };
+            if new > old {
+                events.extend((old + 1..=new).map(|episode| event(episode, true)));
+            } else {
+                events.extend((new + 1..=old).rev().map(|episode| event(episode, false)));
+            }
+        }
+        if events.is_empty() {
+            return Ok(());
+        }
+        let mut result = String::new();
+        for event in &events {
+            result.push_str(&format!("{}\n", event.to_line()));
+        }
+        let mut file = File::options().create(true).append(true).open(&self.filename)?;
+        file.write_all(result.as_bytes())?;
+        file.sync_data()?;
+        self.events.extend(events);
+        Ok(())
+    }
+
+    // pass of the changed item after every change, the rewatch fields are walked back from `base`
+    fn passes(changes: &[Change], base: &AnimeBase) -> Vec<u16> {
+        let mut states = HashMap::new();
+        let mut result = vec![1; changes.len()];
+        for (index, change) in changes.iter().enumerate().rev() {
+            let (id, field, old) = match change {
+                Change::Set { id, field, old, .. } | Change::Pass { id, field, old, .. } => (*id, field, old),
+                _ => continue,
+            };
+            let (rewatches, rewatching) = states
+                .entry(id)
+                .or_insert_with(|| base.get_item(id).map_or((0, false), |item| (item.rewatches, item.rewatching)));
+            result[index] = *rewatches + 1 + *rewatching as u16;
+            match field.as_str() {
+                "rewatches" => *rewatches = old.parse().unwrap_or(*rewatches),
+                "rewatching" => *rewatching = old == "true",
+                _ => {}
+            }
+        }
+        result
+    }
+
+    pub fn timeline(&self, id: u32) -> Vec<&Event> {
+        self.events.iter().filter(|event| event.id == id).collect()
+    }
+
+    pub fn recent(&self, count: usize) -> &[Event] {
+        &self.events[self.events.len().saturating_sub(count)..]
+    }
+
+    // watched episodes and days between the first and the last of them in a pass
+    pub fn pace(&self, id: u32, pass: u16) -> Option<(usize, i64)> {
+        let watched: Vec<_> =
+            self.events.iter().filter(|event| event.id == id && event.pass == pass && event.watched).collect();
+        let (first, last) = (watched.first()?, watched.last()?);
+        Some((watched.len(), (last.time - first.time) / 86400 + 1))
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = match Local.timestamp_opt(self.time, 0).single() {
+            Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+            None => self.time.to_string(),
+        };
+        let state = if self.watched { "watched" } else { "unwatched" };
+        write!(f, "{} pass {} episode {:>3} {} `{}`", time, self.pass, self.episode, state, self.command)
+    }
+}
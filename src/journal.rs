@@ -0,0 +1,194 @@
+use crate::extra::AnimeBase;
+use crate::parser;
+use crate::storage;
+
+use chrono::{Local, TimeZone};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub enum Change {
+    Set { id: u32, field: String, old: String, new: String },
+    // a field set at a pass boundary, a progress reset by a rewatch is not watched
+    Pass { id: u32, field: String, old: String, new: String },
+    // comment and blank lines in front of the item are kept, so undo restores them
+    Insert { index: usize, line: String, leading: Vec<String> },
+    Delete { index: usize, line: String, leading: Vec<String> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub time: i64,
+    pub command: String,
+    pub undone: bool,
+    pub changes: Vec<Change>,
+}
+
+pub struct Journal {
+    filename: String,
+    pub sessions: Vec<Session>,
+}
+
+impl Change {
+    pub fn inverse(&self) -> Change {
+        match self.clone() {
+            Change::Set { id, field, old, new } => Change::Set { id, field, old: new, new: old },
+            Change::Pass { id, field, old, new } => Change::Pass { id, field, old: new, new: old },
+            Change::Insert { index, line, leading } => Change::Delete { index, line, leading },
+            Change::Delete { index, line, leading } => Change::Insert { index, line, leading },
+        }
+    }
+
+    fn parse(line: &str) -> Option<Change> {
+        let mut head = line.splitn(3, ' ');
+        let kind = head.next()?;
+        let target = head.next()?;
+        let other = head.next()?;
+        match kind {
+            "set" | "pass" => {
+                let id = target.parse().ok()?;
+                let raw: Vec<_> = parser::Splitter::new(other, parser::SplitFormat::Anime).collect();
+                let (field, old, new) = match raw[..] {
+                    [field, old, new] => (field.to_owned(), parser::unescape(old), parser::unescape(new)),
+                    _ => return None,
+                };
+                match kind {
+                    "set" => Some(Change::Set { id, field, old, new }),
+                    _ => Some(Change::Pass { id, field, old, new }),
+                }
+            }
+            "insert" => {
+                Some(Change::Insert { index: target.parse().ok()?, line: other.to_owned(), leading: Vec::new() })
+            }
+            "delete" => {
+                Some(Change::Delete { index: target.parse().ok()?, line: other.to_owned(), leading: Vec::new() })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Journal {
+    // journal is kept next to the list file
+    pub fn open(list_filename: &str) -> Result<Journal, io::Error> {
+        let filename = format!("{}.journal", list_filename);
+        let mut sessions: Vec<Session> = Vec::new();
+        // lines in front of an item come before its insert or delete
+        let mut leading = Vec::new();
+        if Path::new(&filename).exists() {
+            for (number, line) in fs::read_to_string(&filename)?.lines().enumerate() {
+                if let Some(text) = line.strip_prefix("leading ") {
+                    match text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+                        Some(text) => leading.push(parser::unescape(text)),
+                        None => warn!("{}: invalid leading line at line {}", filename, number + 1),
+                    }
+                } else if let Some(header) = line.strip_prefix("session ") {
+                    let mut raw = header.splitn(3, ' ');
+                    match (raw.next().map(str::parse), raw.next(), raw.next()) {
+                        (Some(Ok(time)), Some(state), Some(command)) => sessions.push(Session {
+                            time,
+                            command: command.to_owned(),
+                            undone: state == "undone",
+                            changes: Vec::new(),
+                        }),
+                        _ => warn!("{}: invalid session at line {}", filename, number + 1),
+                    }
+                } else {
+                    match (sessions.last_mut(), Change::parse(line)) {
+                        (Some(session), Some(mut change)) => {
+                            if let Change::Insert { leading: lines, .. } | Change::Delete { leading: lines, .. } =
+                                &mut change
+                            {
+                                *lines = std::mem::take(&mut leading);
+                            }
+                            session.changes.push(change);
+                        }
+                        _ => warn!("{}: invalid change at line {}", filename, number + 1),
+                    }
+                }
+            }
+        }
+        Ok(Journal { filename, sessions })
+    }
+
+    pub fn save(&self) -> Result<(), io::Error> {
+        let mut result = String::new();
+        for session in &self.sessions {
+            let state = if session.undone { "undone" } else { "active" };
+            result.push_str(&format!("session {} {} {}\n", session.time, state, session.command));
+            for change in &session.changes {
+                if let Change::Insert { leading, .. } | Change::Delete { leading, .. } = change {
+                    for line in leading {
+                        result.push_str(&format!("leading \"{}\"\n", parser::escape(line)));
+                    }
+                }
+                result.push_str(&format!("{}\n", change));
+            }
+        }
+        storage::write_atomic(&self.filename, 0, result.as_bytes())
+    }
+
+    // a new session drops all undone sessions, they cannot be redone anymore
+    pub fn record(&mut self, command: &str, changes: Vec<Change>) {
+        self.sessions.retain(|session| !session.undone);
+        self.sessions.push(Session {
+            time: Local::now().timestamp(),
+            command: command.to_owned(),
+            undone: false,
+            changes,
+        });
+    }
+
+    // fails with the first change that does not match the list, `base` is left half applied
+    pub fn undo(&mut self, base: &mut AnimeBase, count: usize) -> Result<usize, Change> {
+        let mut result = 0;
+        for session in self.sessions.iter_mut().rev().filter(|s| !s.undone).take(count) {
+            for change in session.changes.iter().rev().map(Change::inverse) {
+                base.apply(&change).ok_or(change)?;
+            }
+            session.undone = true;
+            result += 1;
+        }
+        Ok(result)
+    }
+
+    pub fn redo(&mut self, base: &mut AnimeBase, count: usize) -> Result<usize, Change> {
+        let mut result = 0;
+        for session in self.sessions.iter_mut().filter(|s| s.undone).take(count) {
+            for change in &session.changes {
+                base.apply(change).ok_or_else(|| change.clone())?;
+            }
+            session.undone = false;
+            result += 1;
+        }
+        Ok(result)
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Set { id, field, old, new } => {
+                write!(f, "set {} {} \"{}\" \"{}\"", id, field, parser::escape(old), parser::escape(new))
+            }
+            Change::Pass { id, field, old, new } => {
+                write!(f, "pass {} {} \"{}\" \"{}\"", id, field, parser::escape(old), parser::escape(new))
+            }
+            Change::Insert { index, line, .. } => write!(f, "insert {} {}", index, line),
+            Change::Delete { index, line, .. } => write!(f, "delete {} {}", index, line),
+        }
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = match Local.timestamp_opt(self.time, 0).single() {
+            Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+            None => self.time.to_string(),
+        };
+        let state = if self.undone { " [undone]" } else { "" };
+        write!(f, "{}{} `{}`, changes: {}", time, state, self.command, self.changes.len())
+    }
+}
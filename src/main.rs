@@ -6,16 +6,24 @@ extern crate dirs;
 extern crate tini;
 
 mod base;
+mod exchange;
 mod extra;
+mod history;
+mod journal;
 mod logger;
 mod parser;
+mod storage;
 
+use chrono::{Datelike, Local};
 use colored::*;
 use extra::*;
+use history::History;
+use journal::Journal;
 use regex::Regex;
-use std::env::args;
+use std::env::{self, args};
 use std::fs;
-use std::process::exit;
+use std::io::{self, Write};
+use std::process::{exit, Command};
 use tini::Ini;
 
 // TODO: check & rewrite
@@ -23,32 +31,148 @@ static USAGE_STRING: &str = "\
 >> доступные команды:
  -{n}       -- номер серии -n { стандартное значение = 1 }
  +{n}       -- номер серии +n { стандартное значение = 1 }
+ -v{n}      -- номер тома -n { стандартное значение = 1 }
+ +v{n}      -- номер тома +n { стандартное значение = 1 }
  a          -- добавить элемент [ a/имя | a/\"имя\" ]
+ b{n}       -- восстановить список из резервной копии n { стандартное значение = 1 }
  d          -- удалить элементы { найденые элементы параметром f }
+ e{?}       -- экспортировать список в файл [ e?/\"файл\" | e?/- для вывода на экран ]
+   где ?: j -- json, c -- csv, t -- tsv, m -- myanimelist xml
+   манга и ранобэ не входят в список аниме myanimelist и не экспортируются в m
+ h{?}       -- история просмотра серий
+   h        -- вывести историю найденых элементов
+   hr{n}    -- вывести n последних просмотренных серий { стандартное значение = 10 }
  i          -- вывести весь список
+ j{??}      -- журнал сохранённых изменений
+   j        -- вывести журнал
+   ju{n}    -- отменить n последних сохранений { стандартное значение = 1 }
+   jr{n}    -- повторить n отменённых сохранений { стандартное значение = 1 }
  f{??}      -- поиск по параметру
-   f        -- поиск по названию [ f/\"имя или regex\" ]
+   f        -- поиск по названию, другим названиям и заметке [ f/\"имя или regex\" ]
+   fi{??}   -- по идентификатору { ?? -- идентификатор }
    fs{??}   -- по статусу { ?? -- буква статуса }
      где ??: c -- complete, d -- drop, h -- hold, p -- plan, w -- watch
+   fk{??}   -- по типу { ?? -- буква типа }
+     где ??: t -- tv, m -- movie, o -- ova, n -- ona, s -- special, g -- manga, l -- novel
    fp{??}   -- по номеру серии { ?? -- номер серии }
-   fm{??}   -- по количеству серий в сезоне { ?? -- количество серий в сезоне }
-   fr{??}   -- по оценке { ?? -- оценка }
+   fm{??}   -- по количеству серий в сезоне { ?? -- количество серий в сезоне, ? -- онгоинг, !=? -- не онгоинг }
+   fr{??}   -- по оценке { ?? -- оценка в системе из config.ini }
+     для fi, fp, fm и fr: 7, !=7, <7, <=7, >7, >=7, от..до с любой из границ [ fr>=8 | fp..5 ]
+     онгоинги не подходят под сравнения fm
+   fb{??}   -- по дате начала просмотра { ?? -- период }
+   ff{??}   -- по дате окончания просмотра { ?? -- период }
+     где ??: 2024, 2024-03, 2024-03-15, от..до с любой из границ, ? -- без даты
+   ft       -- по тегу [ ft/тег | ft/!тег -- без тега ]
+   fu       -- по своему полю [ fu/ключ | fu/\"ключ=значение\" | fu/!ключ -- без поля ]
  x{??}      -- фильтровать список полученный после f{??}
+   xi{??}   -- по идентификатору { ?? -- идентификатор }
    xs{??}   -- по статусу { ?? -- буква статуса }
      где ??: c -- complete, d -- drop, h -- hold, p -- plan, w -- watch
+   xk{??}   -- по типу { ?? -- буква типа }
+     где ??: t -- tv, m -- movie, o -- ova, n -- ona, s -- special, g -- manga, l -- novel
    xp{??}   -- по номеру серии { ?? -- номер серии }
-   xm{??}   -- по количеству серий в сезоне { ?? -- количество серий в сезоне }
-   xr{??}   -- по оценке { ?? -- оценка }
+   xm{??}   -- по количеству серий в сезоне { ?? -- количество серий в сезоне, ? -- онгоинг, !=? -- не онгоинг }
+   xr{??}   -- по оценке { ?? -- оценка в системе из config.ini }
+     для xi, xp, xm и xr: 7, !=7, <7, <=7, >7, >=7, от..до с любой из границ [ xr>=8 | xp..5 ]
+     онгоинги не подходят под сравнения xm
+   xb{??}   -- по дате начала просмотра { ?? -- период }
+   xf{??}   -- по дате окончания просмотра { ?? -- период }
+   xt       -- по тегу [ xt/тег | xt/!тег -- без тега ]
+   xu       -- по своему полю [ xu/ключ | xu/\"ключ=значение\" | xu/!ключ -- без поля ]
+ l{??}      -- связи найденых элементов с элементом { ?? -- идентификатор }
+   l?{??}   -- связать [ ls5 -- элемент 5 продолжение найденых ]
+     где ?: s -- продолжение, p -- предыстория, i -- побочная история, m -- основная история, f -- франшиза
+   lu{??}   -- удалить связь с элементом { ?? -- идентификатор }
+   ln       -- заменить найденые элементы следующими непросмотренными во франшизе
+ m          -- время просмотра по статусам, тегам и всего, оставшееся время { найденых элементов или всего списка }
+ n{?}       -- что смотреть дальше { заменяет найденые элементы }
+   n        -- первый из просматриваемых с непросмотренными сериями, затем из запланированных с приоритетом
+               не ниже next_priority из config.ini, по убыванию приоритета
+   nr       -- случайный из них, элемент с приоритетом n выпадает в n + 1 раз чаще
+ o{??}      -- импортировать список из файла [ o??/\"файл\" ]
+   где первый ?: j -- json, c -- csv, t -- tsv, m -- myanimelist xml
+   где второй ?: m -- объединить, r -- заменить, d -- только показать изменения
+   id myanimelist сохраняется в пользовательском поле mal и выгружается при экспорте
+ q          -- запланированные элементы по убыванию приоритета, затем от старых к новым
+ r{?}       -- пересмотр завершённых элементов
+   r        -- начать пересмотр, прогресс начинается с нуля, статус остаётся complete
+   rs       -- прекратить пересмотр
+   rn{n}    -- изменить количество пересмотров на { n }
  s{??}      -- установить параметр
   sn        -- изменить имя на новое [ sn/имя | sn/\"имя\" ]
+  sna       -- изменить имя, оставив старое другим названием [ sna/\"имя\" ]
+  sa        -- добавить другое название [ sa/\"название\" ]
+  su        -- изменить своё поле [ su/\"ключ=значение\" | su/\"ключ=\" -- удалить ]
+  sd        -- удалить другое название [ sd/\"название\" ]
   sm{число} -- изменить максимальный номер серии { ? в случае онгоинга }
   sp{число} -- изменить номер серии на { число }
   sr{число} -- изменить рейтинг на { число }
+    где число: 0-10, 0-100, 0-5 звёзд или 0.0-10.0 в зависимости от scoring в config.ini
+  sl{число} -- изменить длительность серии в минутах { ? -- по типу из config.ini }
+  sq{число} -- изменить приоритет на { число от 0 до 5, 0 -- без приоритета }
   ss{??}    -- изменить статуc на { ?? -- буква статуса }
     где ??: c -- complete, d -- drop, h -- hold, p -- plan, w -- watch
+  sk{??}    -- изменить тип на { ?? -- буква типа }
+    где ??: t -- tv, m -- movie, o -- ova, n -- ona, s -- special, g -- manga, l -- novel
+  sv{число} -- изменить номер тома на { число }
+  svm{число} -- изменить количество томов { ? если неизвестно }
+  sc        -- изменить заметку [ sc/\"текст\" | sc/\"\" -- удалить ]
+  se        -- изменить заметку в $EDITOR
+  sb{дата}  -- изменить дату начала просмотра { ГГГГ-ММ-ДД, без даты -- сегодня, ? -- удалить }
+  sf{дата}  -- изменить дату окончания просмотра { ГГГГ-ММ-ДД, без даты -- сегодня, ? -- удалить }
+  sy{дата}  -- изменить дату выхода первой серии { ГГГГ-ММ-ДД, без даты -- сегодня, ? -- удалить расписание }
+  syi{число} -- изменить количество дней между сериями { стандартное значение = 7 }
+  syt{число} -- изменить ожидаемое количество серий { ? если неизвестно }
+ t{?}       -- теги найденых элементов
+   ta       -- добавить теги [ ta/тег | ta/\"тег 1,тег 2\" ]
+   td       -- удалить теги [ td/тег | td/\"тег 1,тег 2\" ]
+ v          -- вышедшие непросмотренные серии элементов со статусом watch { становятся найдеными элементами }
  w          -- записать изменения в базу
 >> example: 'f/\"One Piece\"/sm?/+5/-/sr7/sp23/ssc/sn/d.gray-man/sm24/w'";
 
+// reload the list if another process has written it, there must be no unsaved changes
+fn refresh(storage: &mut dyn storage::Storage, anime_base: &mut AnimeBase) -> bool {
+    if !storage.is_modified().expect("Cannot check list file") {
+        return true;
+    }
+    let (fresh, errors) = storage.load().expect("Cannot open list file");
+    if errors.is_empty() {
+        *anime_base = fresh;
+    }
+    errors.is_empty()
+}
+
+// the editor gets a temporary file with the note, an empty file removes the note
+fn edit_note(id: u32, note: &str) -> Result<String, io::Error> {
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| "vi".to_owned());
+    let mut editor = editor.split_whitespace();
+    let program = editor.next().unwrap_or("vi");
+    // a fresh file with a random name, an existing file or link in the temp folder is never followed
+    let (path, mut file) = loop {
+        let name = format!("watch-list-note-{}-{:016x}.txt", id, fastrand::u64(..));
+        let path = env::temp_dir().join(name);
+        match fs::File::options().write(true).create_new(true).open(&path) {
+            Ok(file) => break (path, file),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    };
+    let written = file.write_all(note.as_bytes());
+    drop(file);
+    if let Err(err) = written {
+        let _ = fs::remove_file(&path);
+        return Err(err);
+    }
+    let status = Command::new(program).args(editor).arg(&path).status();
+    let result = match status {
+        Ok(status) if status.success() => fs::read_to_string(&path),
+        Ok(status) => Err(io::Error::other(format!("editor exited with {}", status))),
+        Err(err) => Err(err),
+    };
+    let _ = fs::remove_file(&path);
+    Ok(result?.trim_end().to_owned())
+}
+
 fn main() {
     let mut config_file = dirs::home_dir().expect("Cannot detect home folder");
     config_file.push(".config/watch-list/config.ini");
@@ -56,7 +180,27 @@ fn main() {
 
     let log_level: String = config.get("main", "log_level").unwrap_or_else(|| "warn".to_owned());
     let filename: String = config.get("main", "open_file").expect("List file not set");
+    let skip_invalid: bool = config.get("main", "skip_invalid").unwrap_or(false);
+    let backups: usize = config.get("main", "backups").unwrap_or(3);
+    let storage_kind: String = config.get("main", "storage").unwrap_or_else(|| "text".to_owned());
+    let scoring: String = config.get("main", "scoring").unwrap_or_else(|| "10".to_owned());
+    let next_priority: u8 = config.get("main", "next_priority").unwrap_or(3);
+    let show_fields: String = config.get("main", "show_fields").unwrap_or_else(|| "*".to_owned());
+    let columns = exchange::Columns::from(&config);
     logger::init(&log_level).unwrap();
+    AnimeBase::show_custom(base::parse_tags(&show_fields));
+    let durations = base::MediaType::ALL.iter().filter_map(|kind| {
+        let duration: Option<u16> = config.get("durations", &kind.to_string());
+        duration.filter(|duration| *duration != 0).map(|duration| (*kind, duration))
+    });
+    base::MediaType::init_durations(durations.collect());
+    match base::Scoring::from(&scoring) {
+        Some(scoring) => scoring.init(),
+        None => {
+            println!("{}", format!("> unknown scoring system `{}`, expected 10, 100, 5 or decimal", scoring).red());
+            exit(1);
+        }
+    }
 
     let args: Vec<_> = args().collect();
     if args.len() != 2 {
@@ -69,14 +213,33 @@ fn main() {
     let mut update_flag = false;
     let mut save_flag = false;
     let mut delete_flag = false;
+    let mut import_flag = false;
     let mut filter_command = false;
+    let mut read_only = false;
     let mut parity_item = 0;
 
-    debug!("read list from file `{}`", filename);
-    let mut anime_base = AnimeBase::new();
-    let buffer = fs::read_to_string(&filename).expect("Cannot open list file");
-    for string in buffer.lines() {
-        anime_base.push(base::Item::new(string));
+    debug!("read list from {} storage `{}`", storage_kind, filename);
+    let mut storage = storage::open(&storage_kind, &filename, backups).expect("Cannot open list storage");
+    let (mut anime_base, errors) = storage.load().expect("Cannot open list file");
+    let mut journal = Journal::open(&filename).expect("Cannot open journal file");
+    if anime_base.version > base::FORMAT_VERSION {
+        let result = format!(
+            "> list format {} is newer than supported {}, list is read-only",
+            anime_base.version,
+            base::FORMAT_VERSION
+        );
+        println!("{}", result.red());
+        read_only = true;
+    }
+    if !errors.is_empty() {
+        for err in &errors {
+            println!("{}", format!(">  error: {}: {}", filename, err).red());
+        }
+        if !skip_invalid && !read_only {
+            exit(1);
+        }
+        println!("{}", format!("> skipped {} invalid line(s), list is read-only", errors.len()).red());
+        read_only = true;
     }
 
     let mut colorizer = |s: String| -> ColoredString {
@@ -90,7 +253,7 @@ fn main() {
 
     debug!("command list:");
     let mut iterator = parser::Splitter::new(arg_line, parser::SplitFormat::Commands);
-    let mut anime_list: Vec<usize> = Vec::new();
+    let mut anime_list: Vec<u32> = Vec::new();
     let mut commands = Vec::new();
     // collect all input commands
     while let Some(item) = iterator.next() {
@@ -104,15 +267,16 @@ fn main() {
         match cmd {
             ExecCmd::Increment(value) => {
                 debug!("command inc by `{}`", value);
-                for index in &anime_list {
-                    anime_base.progress_increment_by(*index, value).expect("Problem with increment_by");
+                // setters skip items missing from the list
+                for id in &anime_list {
+                    anime_base.progress_increment_by(*id, value);
                 }
                 update_flag = true;
             }
             ExecCmd::Decrement(value) => {
                 debug!("command dec by `{}`", value);
-                for index in &anime_list {
-                    anime_base.progress_decrement_by(*index, value).expect("Problem with decrement_by");
+                for id in &anime_list {
+                    anime_base.progress_decrement_by(*id, value);
                 }
                 update_flag = true;
             }
@@ -120,17 +284,19 @@ fn main() {
                 debug!("command new anime `{}`", name);
                 let new_item = anime_base.append(&name);
                 anime_list.push(new_item);
-                let result = format!("> append: {}", anime_base.format_by_index(new_item)).red();
-                println!("{}", result);
+                if let Some(item) = anime_base.format_by_id(new_item) {
+                    println!("{}", format!("> append: {}", item).red());
+                }
             }
             ExecCmd::Delete => {
                 debug!("command delete item");
-                let mut remove_list = anime_list.clone();
-                remove_list.sort_by(|a, b| b.cmp(a));
-                for index in remove_list {
-                    let result = format!("> delete: {}", anime_base.format_by_index(index)).red();
-                    anime_base.list.swap_remove(index);
-                    println!("{}", result);
+                // the same item can be found several times, it is deleted once
+                let mut seen = std::collections::HashSet::new();
+                anime_list.retain(|id| seen.insert(*id));
+                for id in anime_list.drain(..) {
+                    if let Some(item) = anime_base.remove(id) {
+                        println!("{}", format!("> delete: {}", anime_base.format(&item)).red());
+                    }
                 }
                 delete_flag = true;
             }
@@ -144,30 +310,35 @@ fn main() {
             ExecCmd::Find(regex) => {
                 debug!("command find `{}`", regex);
                 let re = Regex::new(&regex).expect("Problem with regex");
-                for (index, item) in anime_base.list.iter().enumerate() {
-                    if re.is_match(&item.name) {
-                        anime_list.push(index);
+                for item in &anime_base.list {
+                    let alias = item.aliases.iter().find(|alias| re.is_match(alias));
+                    if re.is_match(&item.name) || alias.is_some() || re.is_match(&item.note) {
+                        anime_list.push(item.id);
                         if !filter_command {
-                            let item = format!(">  found: {}", anime_base.format(item));
-                            println!("{}", colorizer(item));
+                            let line = match alias {
+                                Some(alias) if !re.is_match(&item.name) => {
+                                    format!(">  found: {} (as '{}')", anime_base.format(item), alias)
+                                }
+                                _ => format!(">  found: {}", anime_base.format(item)),
+                            };
+                            println!("{}", colorizer(line));
+                            for line in anime_base.format_related(item) {
+                                println!("{}", format!(">   {}", line).red());
+                            }
                         }
                     }
                 }
             }
             ExecCmd::FindParam(param) => {
                 debug!("command find by param `{:?}`", param);
-                for (index, item) in anime_base.list.iter().enumerate() {
-                    let is_match = match param {
-                        ParamType::Status(value) => item.status == value,
-                        ParamType::Progress(value) => item.progress == value,
-                        ParamType::Maximum(value) => item.maximum == value,
-                        ParamType::Rate(value) => item.rate == value,
-                    };
-                    if is_match {
-                        anime_list.push(index);
+                for item in &anime_base.list {
+                    if param.matches(item) {
+                        anime_list.push(item.id);
                         if !filter_command {
-                            let item = format!(">  found: {}", anime_base.format(item));
-                            println!("{}", colorizer(item));
+                            println!("{}", colorizer(format!(">  found: {}", anime_base.format(item))));
+                            for line in anime_base.format_related(item) {
+                                println!("{}", format!(">   {}", line).red());
+                            }
                         }
                     }
                 }
@@ -175,16 +346,10 @@ fn main() {
             ExecCmd::FilterParam(param) => {
                 debug!("command filter by param `{:?}`", param);
                 let mut new_anime_list = Vec::new();
-                for index in anime_list.into_iter() {
-                    let item = anime_base.get_item(index).expect("Cannot get item");
-                    let is_match = match param {
-                        ParamType::Status(value) => item.status == value,
-                        ParamType::Progress(value) => item.progress == value,
-                        ParamType::Maximum(value) => item.maximum == value,
-                        ParamType::Rate(value) => item.rate == value,
-                    };
-                    if is_match {
-                        new_anime_list.push(index);
+                for id in anime_list.into_iter() {
+                    // items missing from the list are dropped
+                    if let Some(item) = anime_base.get_item(id).filter(|item| param.matches(item)) {
+                        new_anime_list.push(id);
                         let item = format!("> filter: {}", anime_base.format(item));
                         println!("{}", colorizer(item));
                     }
@@ -193,55 +358,558 @@ fn main() {
             }
             ExecCmd::Maximum(value) => {
                 debug!("command series limit to `{}`", value);
-                for index in &anime_list {
-                    anime_base.set_maximum(*index, value).expect("Problem with set_maximum");
+                for id in &anime_list {
+                    anime_base.set_maximum(*id, value);
                 }
                 update_flag = true;
             }
-            ExecCmd::Rename(new_name) => {
+            ExecCmd::Rename(new_name, keep) => {
                 debug!("command new name `{}`", new_name);
-                for index in &anime_list {
-                    anime_base.set_name(*index, &new_name).expect("Problem with set_name");
+                for id in &anime_list {
+                    anime_base.set_name(*id, &new_name, keep);
+                }
+                update_flag = true;
+            }
+            ExecCmd::Link(kind, target) => {
+                debug!("command link `{}` `{}`", kind, target);
+                for id in &anime_list {
+                    if anime_base.link(*id, kind, target).is_none() {
+                        let result =
+                            format!("> cannot link #{} as {} of: {}", target, kind, anime_base.format_or_id(*id));
+                        println!("{}", result.red());
+                    }
+                }
+                update_flag = true;
+            }
+            ExecCmd::Unlink(target) => {
+                debug!("command unlink `{}`", target);
+                for id in &anime_list {
+                    anime_base.unlink(*id, target);
+                }
+                update_flag = true;
+            }
+            ExecCmd::NextInFranchise => {
+                debug!("command next in franchise");
+                let mut next_list = Vec::new();
+                for id in &anime_list {
+                    match anime_base.next_in_franchise(*id) {
+                        Some(next) if !next_list.contains(&next) => {
+                            next_list.push(next);
+                            if let Some(item) = anime_base.format_by_id(next) {
+                                println!("{}", colorizer(format!(">   next: {}", item)));
+                            }
+                        }
+                        Some(_) => {}
+                        None => {
+                            let result = format!("> nothing left in franchise of: {}", anime_base.format_or_id(*id));
+                            println!("{}", result.red());
+                        }
+                    }
+                }
+                anime_list = next_list;
+            }
+            ExecCmd::Custom(key, value) => {
+                debug!("command custom field `{}` = `{}`", key, value);
+                for id in &anime_list {
+                    anime_base.set_custom(*id, &key, &value);
+                }
+                update_flag = true;
+            }
+            ExecCmd::AddAlias(alias) => {
+                debug!("command add alias `{}`", alias);
+                for id in &anime_list {
+                    anime_base.add_alias(*id, &alias);
+                }
+                update_flag = true;
+            }
+            ExecCmd::RemoveAlias(alias) => {
+                debug!("command remove alias `{}`", alias);
+                for id in &anime_list {
+                    anime_base.remove_alias(*id, &alias);
                 }
                 update_flag = true;
             }
             ExecCmd::Progress(value) => {
                 debug!("command progress `{}`", value);
-                for index in &anime_list {
-                    anime_base.set_progress(*index, value).expect("Problem with set_progress");
+                for id in &anime_list {
+                    anime_base.set_progress(*id, value);
                 }
                 update_flag = true;
             }
             ExecCmd::Status(status) => {
                 debug!("command status `{:?}`", status);
-                for index in &anime_list {
-                    anime_base.set_status(*index, status).expect("Problem with set_status");
+                for id in &anime_list {
+                    anime_base.set_status(*id, status);
+                }
+                update_flag = true;
+            }
+            ExecCmd::Rewatch => {
+                debug!("command start rewatch");
+                for id in &anime_list {
+                    if anime_base.start_rewatch(*id).is_none() {
+                        let result = format!("> cannot rewatch: {}", anime_base.format_or_id(*id));
+                        println!("{}", result.red());
+                    }
+                }
+                update_flag = true;
+            }
+            ExecCmd::StopRewatch => {
+                debug!("command stop rewatch");
+                for id in &anime_list {
+                    if anime_base.stop_rewatch(*id).is_none() {
+                        let result = format!("> not rewatching: {}", anime_base.format_or_id(*id));
+                        println!("{}", result.red());
+                    }
+                }
+                update_flag = true;
+            }
+            ExecCmd::Rewatches(value) => {
+                debug!("command rewatches `{}`", value);
+                for id in &anime_list {
+                    anime_base.set_rewatches(*id, value);
+                }
+                update_flag = true;
+            }
+            ExecCmd::Kind(kind) => {
+                debug!("command media type `{:?}`", kind);
+                for id in &anime_list {
+                    anime_base.set_kind(*id, kind);
+                }
+                update_flag = true;
+            }
+            ExecCmd::Volume(value) => {
+                debug!("command volume `{}`", value);
+                for id in &anime_list {
+                    anime_base.set_volume(*id, value);
+                }
+                update_flag = true;
+            }
+            ExecCmd::VolumeMaximum(value) => {
+                debug!("command volumes limit to `{}`", value);
+                for id in &anime_list {
+                    anime_base.set_volumes(*id, value);
+                }
+                update_flag = true;
+            }
+            ExecCmd::VolumeIncrement(value) => {
+                debug!("command volume inc by `{}`", value);
+                for id in &anime_list {
+                    anime_base.volume_increment_by(*id, value);
+                }
+                update_flag = true;
+            }
+            ExecCmd::VolumeDecrement(value) => {
+                debug!("command volume dec by `{}`", value);
+                for id in &anime_list {
+                    anime_base.volume_decrement_by(*id, value);
                 }
                 update_flag = true;
             }
             ExecCmd::Rate(value) => {
                 debug!("command rate `{}`", value);
-                for index in &anime_list {
-                    anime_base.set_rate(*index, value).expect("Problem with set_rate");
+                for id in &anime_list {
+                    anime_base.set_rate(*id, value);
+                }
+                update_flag = true;
+            }
+            ExecCmd::Started(date) => {
+                debug!("command start date `{:?}`", date);
+                for id in &anime_list {
+                    anime_base.set_started(*id, date);
+                }
+                update_flag = true;
+            }
+            ExecCmd::Finished(date) => {
+                debug!("command finish date `{:?}`", date);
+                for id in &anime_list {
+                    anime_base.set_finished(*id, date);
+                }
+                update_flag = true;
+            }
+            ExecCmd::Airing(date) => {
+                debug!("command airing start `{:?}`", date);
+                for id in &anime_list {
+                    anime_base.set_airing(*id, date);
+                }
+                update_flag = true;
+            }
+            ExecCmd::AiringInterval(value) => {
+                debug!("command airing interval `{}`", value);
+                for id in &anime_list {
+                    if anime_base.set_airing_interval(*id, value).is_none() {
+                        let result = format!("> no airing schedule: {}", anime_base.format_or_id(*id));
+                        println!("{}", result.red());
+                    }
+                }
+                update_flag = true;
+            }
+            ExecCmd::AiringTotal(value) => {
+                debug!("command airing total `{}`", value);
+                for id in &anime_list {
+                    if anime_base.set_airing_total(*id, value).is_none() {
+                        let result = format!("> no airing schedule: {}", anime_base.format_or_id(*id));
+                        println!("{}", result.red());
+                    }
+                }
+                update_flag = true;
+            }
+            ExecCmd::Available => {
+                debug!("command available episodes");
+                let today = Local::now().date_naive();
+                let mut available = Vec::new();
+                for item in &anime_base.list {
+                    if item.status != base::Status::Watch {
+                        continue;
+                    }
+                    let airing = match item.airing {
+                        Some(airing) => airing,
+                        None if item.maximum == base::SeriesCounter::OnGoing => {
+                            let result = format!("> no airing schedule: {}", anime_base.format(item));
+                            println!("{}", result.red());
+                            continue;
+                        }
+                        None => continue,
+                    };
+                    let aired = item.aired(today).unwrap_or_default();
+                    println!("{}", colorizer(format!("> airing: {}", anime_base.format(item))));
+                    if aired > item.progress {
+                        // aired episodes replace the found items
+                        available.push(item.id);
+                        let result = format!(">   episodes {} - {} are out", item.progress + 1, aired);
+                        println!("{}", result.red());
+                    }
+                    match airing.next(today) {
+                        Some(date) if item.maximum != base::SeriesCounter::Value(aired) => {
+                            let result = format!(">   episode {} airs on {} {}", aired + 1, date.weekday(), date);
+                            println!("{}", result.red());
+                        }
+                        _ => println!("{}", ">   all episodes are out".red()),
+                    }
+                }
+                anime_list = available;
+            }
+            ExecCmd::Priority(value) => {
+                debug!("command priority `{}`", value);
+                for id in &anime_list {
+                    anime_base.set_priority(*id, value);
+                }
+                update_flag = true;
+            }
+            ExecCmd::Duration(value) => {
+                debug!("command duration `{:?}`", value);
+                for id in &anime_list {
+                    anime_base.set_duration(*id, value);
+                }
+                update_flag = true;
+            }
+            ExecCmd::WatchTime => {
+                debug!("command watch time");
+                // found items or the whole list
+                let ids: Vec<_> = if anime_list.is_empty() {
+                    anime_base.list.iter().map(|item| item.id).collect()
+                } else {
+                    anime_list.clone()
+                };
+                let time = anime_base.watch_time(&ids);
+                println!("{}", colorizer(format!(">   time: {} watched", format_minutes(time.total))));
+                for (status, minutes) in &time.by_status {
+                    println!("{}", format!(">   {:>8}: {}", status.to_string(), format_minutes(*minutes)).red());
+                }
+                for (tag, minutes) in &time.by_tag {
+                    println!("{}", format!(">   tag {}: {}", tag, format_minutes(*minutes)).red());
+                }
+                for (status, minutes) in &time.remaining {
+                    let result = format!(">   time: {} left of {} items", format_minutes(*minutes), status);
+                    println!("{}", colorizer(result));
+                }
+                if time.unknown != 0 {
+                    let result = format!(">   {} ongoing item(s) of unknown length are not counted", time.unknown);
+                    println!("{}", result.red());
+                }
+            }
+            ExecCmd::Queue => {
+                debug!("command print plan queue");
+                for (number, item) in anime_base.queue().into_iter().enumerate() {
+                    anime_list.push(item.id);
+                    println!("{}", colorizer(format!(">  queue: {:>3}. {}", number + 1, anime_base.format(item))));
+                }
+            }
+            ExecCmd::Next(random) => {
+                debug!("command next random `{}`", random);
+                match anime_base.pick_next(next_priority, Local::now().date_naive(), random) {
+                    Some(id) => {
+                        anime_list = vec![id];
+                        if let Some(item) = anime_base.format_by_id(id) {
+                            println!("{}", colorizer(format!(">   next: {}", item)));
+                        }
+                    }
+                    None => println!("{}", "> nothing to watch next".red()),
+                }
+            }
+            ExecCmd::AddTags(tags) => {
+                debug!("command add tags `{:?}`", tags);
+                for id in &anime_list {
+                    anime_base.add_tags(*id, &tags);
+                }
+                update_flag = true;
+            }
+            ExecCmd::RemoveTags(tags) => {
+                debug!("command remove tags `{:?}`", tags);
+                for id in &anime_list {
+                    anime_base.remove_tags(*id, &tags);
+                }
+                update_flag = true;
+            }
+            ExecCmd::Note(note) => {
+                debug!("command note `{}`", note);
+                for id in &anime_list {
+                    anime_base.set_note(*id, &note);
+                }
+                update_flag = true;
+            }
+            ExecCmd::EditNote => {
+                debug!("command edit note");
+                for id in &anime_list {
+                    let note = match anime_base.get_item(*id) {
+                        Some(item) => item.note.clone(),
+                        None => continue,
+                    };
+                    match edit_note(*id, &note) {
+                        Ok(note) => {
+                            anime_base.set_note(*id, &note);
+                        }
+                        Err(err) => println!("{}", format!("> cannot edit note: {}", err).red()),
+                    }
                 }
                 update_flag = true;
             }
             ExecCmd::Write => {
                 debug!("command write changes");
-                let mut file = fs::File::create(&filename).expect("Cannot write list file");
-                anime_base.write_to_file(&mut file).expect("Cannot write to list file");
+                if read_only {
+                    println!("{}", "> list is read-only, nothing saved".red());
+                    continue;
+                }
+                let _lock = storage.lock().expect("Cannot lock list file");
+                let mut changes = std::mem::take(&mut anime_base.changes);
+                if storage.is_modified().expect("Cannot check list file") {
+                    let (mut fresh, errors) = storage.load().expect("Cannot open list file");
+                    let result = match errors.first() {
+                        _ if fresh.version > base::FORMAT_VERSION => Err(format!("format {}", fresh.version)),
+                        Some(err) => Err(format!("invalid line: {}", err)),
+                        None => fresh.rebase(&changes).map_err(|change| format!("conflicting change: {}", change)),
+                    };
+                    let ids = match result {
+                        Ok(ids) => ids,
+                        Err(conflict) => {
+                            let result = format!("> list was changed by another process, nothing saved, {}", conflict);
+                            println!("{}", result.red());
+                            // the storage now holds the newer list, a later write would overwrite it
+                            anime_base.changes = changes;
+                            read_only = true;
+                            continue;
+                        }
+                    };
+                    for id in anime_list.iter_mut() {
+                        *id = *ids.get(id).unwrap_or(id);
+                    }
+                    // items deleted by the other process are dropped
+                    anime_list.retain(|id| fresh.get_item(*id).is_some());
+                    println!("{}", "> list was changed by another process, changes applied again".red());
+                    changes = std::mem::take(&mut fresh.changes);
+                    anime_base = fresh;
+                }
+                storage.apply(&anime_base, &changes).expect("Cannot write list file");
+                History::open(&filename)
+                    .and_then(|mut history| history.record(arg_line, &changes, &anime_base))
+                    .expect("Cannot write history file");
+                if !changes.is_empty() {
+                    journal = Journal::open(&filename).expect("Cannot open journal file");
+                    journal.record(arg_line, changes);
+                    journal.save().expect("Cannot write journal file");
+                }
                 save_flag = true;
             }
+            ExecCmd::Export(format, path) => {
+                debug!("command export `{:?}` to `{}`", format, path);
+                for item in exchange::unsupported(format, &anime_base) {
+                    let item = format!("> not exported: {}", anime_base.format(item));
+                    println!("{}", colorizer(item));
+                }
+                let result = exchange::export(format, &anime_base, &columns);
+                if path == "-" {
+                    println!("{}", result);
+                } else {
+                    match fs::write(&path, result) {
+                        Ok(()) => println!("{}", format!("> exported to `{}`", path).red()),
+                        Err(err) => println!("{}", format!("> cannot export to `{}`: {}", path, err).red()),
+                    }
+                }
+            }
+            ExecCmd::Import(format, mode, path) => {
+                debug!("command import `{:?}` from `{}` with `{:?}`", format, path, mode);
+                let text = match fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        println!("{}", format!("> cannot import from `{}`: {}", path, err).red());
+                        continue;
+                    }
+                };
+                let (items, errors) = exchange::import(format, &text, &columns);
+                for err in &errors {
+                    println!("{}", format!(">  error: {}: {}", path, err).red());
+                }
+                for line in anime_base.import(items, mode) {
+                    let item = format!("> import: {}", line);
+                    println!("{}", colorizer(item));
+                }
+                // replace deletes items
+                anime_list.retain(|id| anime_base.get_item(*id).is_some());
+                if mode != ImportMode::DryRun {
+                    import_flag = true;
+                }
+            }
+            ExecCmd::Journal => {
+                debug!("command print journal");
+                for (number, session) in journal.sessions.iter().enumerate() {
+                    let item = format!("> journal: {:>3}. {}", number + 1, session);
+                    println!("{}", colorizer(item));
+                }
+            }
+            ExecCmd::Undo(_) | ExecCmd::Redo(_) if read_only || !anime_base.changes.is_empty() => {
+                println!("{}", "> undo and redo need a valid list without unsaved changes".red());
+            }
+            ExecCmd::Undo(count) => {
+                debug!("command undo `{}` session(s)", count);
+                let _lock = storage.lock().expect("Cannot lock list file");
+                if !refresh(storage.as_mut(), &mut anime_base) {
+                    println!("{}", "> list was changed by another process and has invalid lines".red());
+                    continue;
+                }
+                journal = Journal::open(&filename).expect("Cannot open journal file");
+                let undone = match journal.undo(&mut anime_base, count) {
+                    Ok(undone) => undone,
+                    Err(change) => {
+                        let result = format!("> journal does not match the list, nothing changed, at: {}", change);
+                        println!("{}", result.red());
+                        // drop the half applied sessions
+                        anime_base = storage.load().expect("Cannot open list file").0;
+                        journal = Journal::open(&filename).expect("Cannot open journal file");
+                        continue;
+                    }
+                };
+                let changes = std::mem::take(&mut anime_base.changes);
+                storage.apply(&anime_base, &changes).expect("Cannot write list file");
+                History::open(&filename)
+                    .and_then(|mut history| history.record(arg_line, &changes, &anime_base))
+                    .expect("Cannot write history file");
+                journal.save().expect("Cannot write journal file");
+                anime_list.clear();
+                println!("{}", format!("> undone {} session(s)", undone).red());
+            }
+            ExecCmd::Redo(count) => {
+                debug!("command redo `{}` session(s)", count);
+                let _lock = storage.lock().expect("Cannot lock list file");
+                if !refresh(storage.as_mut(), &mut anime_base) {
+                    println!("{}", "> list was changed by another process and has invalid lines".red());
+                    continue;
+                }
+                journal = Journal::open(&filename).expect("Cannot open journal file");
+                let redone = match journal.redo(&mut anime_base, count) {
+                    Ok(redone) => redone,
+                    Err(change) => {
+                        let result = format!("> journal does not match the list, nothing changed, at: {}", change);
+                        println!("{}", result.red());
+                        // drop the half applied sessions
+                        anime_base = storage.load().expect("Cannot open list file").0;
+                        journal = Journal::open(&filename).expect("Cannot open journal file");
+                        continue;
+                    }
+                };
+                let changes = std::mem::take(&mut anime_base.changes);
+                storage.apply(&anime_base, &changes).expect("Cannot write list file");
+                History::open(&filename)
+                    .and_then(|mut history| history.record(arg_line, &changes, &anime_base))
+                    .expect("Cannot write history file");
+                journal.save().expect("Cannot write journal file");
+                anime_list.clear();
+                println!("{}", format!("> redone {} session(s)", redone).red());
+            }
+            ExecCmd::History => {
+                debug!("command print history");
+                let history = History::open(&filename).expect("Cannot open history file");
+                for id in &anime_list {
+                    let item = format!("> history: {}", anime_base.format_or_id(*id));
+                    println!("{}", colorizer(item));
+                    for event in history.timeline(*id) {
+                        println!("{}", format!(">   {}", event).red());
+                    }
+                    let passes = anime_base.get_item(*id).map_or(1, |item| item.pass());
+                    for pass in 1..=passes {
+                        if let Some((episodes, days)) = history.pace(*id, pass) {
+                            let pace = episodes as f32 / days as f32;
+                            let result = format!(
+                                ">   pass {}: {} episode(s) in {} day(s), {:.1} per day",
+                                pass, episodes, days, pace
+                            );
+                            println!("{}", result.red());
+                        }
+                    }
+                }
+            }
+            ExecCmd::Recent(count) => {
+                debug!("command print `{}` recent episode(s)", count);
+                let history = History::open(&filename).expect("Cannot open history file");
+                for event in history.recent(count) {
+                    let name = anime_base.get_item(event.id).map_or("<deleted>", |item| item.name.as_str());
+                    let item = format!(">  recent: {} #{} '{}'", event, event.id, name);
+                    println!("{}", colorizer(item));
+                }
+            }
+            ExecCmd::Restore(_) if !anime_base.changes.is_empty() => {
+                println!("{}", "> restore needs a list without unsaved changes".red());
+            }
+            ExecCmd::Restore(number) => {
+                debug!("command restore backup `{}`", number);
+                let _lock = storage.lock().expect("Cannot lock list file");
+                match storage.restore(number) {
+                    Ok((restored, errors)) => {
+                        anime_base = restored;
+                        anime_list.clear();
+                        println!("{}", format!("> restored from backup {}", number).red());
+                        // the same checks as for the list on start
+                        read_only = anime_base.version > base::FORMAT_VERSION || !errors.is_empty();
+                        if anime_base.version > base::FORMAT_VERSION {
+                            let result = format!(
+                                "> list format {} is newer than supported {}, list is read-only",
+                                anime_base.version,
+                                base::FORMAT_VERSION
+                            );
+                            println!("{}", result.red());
+                        }
+                        if !errors.is_empty() {
+                            for err in &errors {
+                                println!("{}", format!(">  error: {}: {}", filename, err).red());
+                            }
+                            println!("{}", format!("> {} invalid line(s), list is read-only", errors.len()).red());
+                        }
+                    }
+                    Err(err) => println!("{}", format!("> cannot restore backup {}: {}", number, err).red()),
+                }
+            }
             ExecCmd::Error(kind) => warn!("`{}`: {:?}", item, kind),
         };
     }
     if update_flag {
-        for index in &anime_list {
-            let item = format!("> update: {}", anime_base.format_by_index(*index));
-            println!("{}", colorizer(item))
+        for id in &anime_list {
+            if let Some(item) = anime_base.format_by_id(*id) {
+                println!("{}", colorizer(format!("> update: {}", item)))
+            }
         }
     }
     if save_flag {
-        println!("{}", if update_flag || delete_flag { "> changes saved".red() } else { "> nothing to save".red() });
+        println!(
+            "{}",
+            if update_flag || delete_flag || import_flag { "> changes saved".red() } else { "> nothing to save".red() }
+        );
     }
 }
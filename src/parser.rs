@@ -3,6 +3,7 @@ enum StateMachine {
     Normal,
     Separator,
     Text,
+    Escape,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -13,14 +14,20 @@ pub enum SplitFormat {
 
 pub struct Splitter<'a> {
     start: usize,
+    position: usize,
     state: StateMachine,
     string: &'a str,
     fmt: SplitFormat,
 }
 
 impl<'a> Splitter<'a> {
-    pub fn new(string: &'a str, fmt: SplitFormat) -> Splitter {
-        Splitter { start: 0, state: StateMachine::Normal, string, fmt }
+    pub fn new(string: &'a str, fmt: SplitFormat) -> Splitter<'a> {
+        Splitter { start: 0, position: 0, state: StateMachine::Normal, string, fmt }
+    }
+
+    // byte offset of the last returned token
+    pub fn position(&self) -> usize {
+        self.position
     }
 
     fn anime_cycle(state: StateMachine, character: char) -> (StateMachine, Option<char>) {
@@ -34,7 +41,9 @@ impl<'a> Splitter<'a> {
             (Separator, '"') => (Text, None),
             (Separator, _) => (Normal, Some(character)),
             (Text, '"') => (Normal, None),
+            (Text, '\\') => (Escape, Some(character)),
             (Text, _) => (Text, Some(character)),
+            (Escape, _) => (Text, Some(character)),
         }
     }
 
@@ -49,50 +58,121 @@ impl<'a> Splitter<'a> {
             (Separator, '"') => (Text, None),
             (Separator, _) => (Normal, Some(character)),
             (Text, '"') => (Normal, None),
+            (Text, '\\') => (Escape, Some(character)),
             (Text, _) => (Text, Some(character)),
+            (Escape, _) => (Text, Some(character)),
+        }
+    }
+}
+
+// quoted text keeps `\"`, `\\` and `\n` escaped, tokens are unescaped by the caller
+pub fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// unknown escapes are kept as they are, so regular expressions pass through
+pub fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(character) = chars.next() {
+        match (character, chars.clone().next()) {
+            ('\\', Some(next @ ('\\' | '"'))) => {
+                result.push(next);
+                chars.next();
+            }
+            ('\\', Some('n')) => {
+                result.push('\n');
+                chars.next();
+            }
+            _ => result.push(character),
         }
     }
+    result
 }
 
 impl<'a> Iterator for Splitter<'a> {
     type Item = &'a str;
 
     fn next(&mut self) -> Option<&'a str> {
-        fn substr(str: &str, start: usize, stop: usize) -> &str {
-            let (s, _) = str.char_indices().nth(start).unwrap();
-            let (e, _) = str.char_indices().nth(stop).unwrap();
-            &str[s..e]
-        }
-        for (index, character) in self.string.chars().skip(self.start).enumerate() {
+        let base = self.start;
+        let mut begin = base;
+        for (offset, character) in self.string[base..].char_indices() {
             let (new_state, new_char) = match self.fmt {
                 SplitFormat::Anime => Splitter::anime_cycle(self.state, character),
                 SplitFormat::Commands => Splitter::command_cycle(self.state, character),
             };
-            let index = index + self.start;
+            let index = base + offset;
+            let after = index + character.len_utf8();
             self.state = new_state;
             match (new_state, new_char) {
                 (StateMachine::Separator, None) => {
-                    let last_start = self.start;
-                    self.start = index + 1;
-                    if index - last_start > 0 {
-                        return Some(substr(self.string, last_start, index));
+                    if index > begin {
+                        self.start = after;
+                        self.position = begin;
+                        return Some(&self.string[begin..index]);
                     }
+                    begin = after;
                 }
-                (StateMachine::Text, None) => self.start = index + 1,
+                (StateMachine::Text, None) => begin = after,
                 (StateMachine::Normal, None) => {
-                    let result = substr(self.string, self.start, index - 1);
-                    self.start = index + 1;
-                    return Some(result);
+                    self.start = after;
+                    self.position = begin;
+                    return Some(&self.string[begin..index]);
                 }
                 _ => {}
             };
         }
-        if self.start < self.string.len() {
-            let (s, _) = self.string.char_indices().nth(self.start).unwrap();
-            self.start = self.string.len();
-            Some(&self.string[s..])
+        self.start = self.string.len();
+        if begin < self.string.len() {
+            self.position = begin;
+            Some(&self.string[begin..])
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(s: &str, fmt: SplitFormat) -> Vec<&str> {
+        Splitter::new(s, fmt).collect()
+    }
+
+    #[test]
+    fn splitter_keeps_escapes_in_quoted_text() {
+        let line = r#""Say \"hi\" \\o/" watch note "a\nb""#;
+        assert_eq!(split(line, SplitFormat::Anime), [r#"Say \"hi\" \\o/"#, "watch", "note", r"a\nb"]);
+        assert_eq!(unescape(r#"Say \"hi\" \\o/"#), r#"Say "hi" \o/"#);
+    }
+
+    #[test]
+    fn splitter_ends_text_after_escaped_backslash() {
+        let line = r#""C:\\" watch"#;
+        assert_eq!(split(line, SplitFormat::Anime), [r"C:\\", "watch"]);
+    }
+
+    #[test]
+    fn splitter_keeps_separators_in_quoted_commands() {
+        assert_eq!(split(r#"f/"a/\"b\""/+"#, SplitFormat::Commands), ["f", r#"a/\"b\""#, "+"]);
+    }
+
+    #[test]
+    fn splitter_reports_token_position() {
+        let line = r#""A"  watch"#;
+        let mut iter = Splitter::new(line, SplitFormat::Anime);
+        assert_eq!(iter.next(), Some("A"));
+        assert_eq!(iter.position(), 1);
+        assert_eq!(iter.next(), Some("watch"));
+        assert_eq!(iter.position(), 5);
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        let text = "quote \" backslash \\ line\nend";
+        assert_eq!(escape(text), r#"quote \" backslash \\ line\nend"#);
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(unescape(r"\d+\.\w"), r"\d+\.\w");
+    }
+}
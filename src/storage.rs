@@ -0,0 +1,325 @@
+use crate::base;
+use crate::extra::AnimeBase;
+use crate::journal::Change;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+pub trait Storage {
+    fn load(&mut self) -> Result<(AnimeBase, Vec<base::ParseError>), io::Error>;
+    fn save(&mut self, base: &AnimeBase) -> Result<(), io::Error>;
+    // store the changes made since the last load or save, `base` already contains them
+    fn apply(&mut self, base: &AnimeBase, changes: &[Change]) -> Result<(), io::Error>;
+    // advisory lock for a write, released when the file is dropped
+    fn lock(&self) -> Result<File, io::Error>;
+    // another process has written the list since the last load or save
+    fn is_modified(&mut self) -> Result<bool, io::Error>;
+
+    fn restore(&mut self, _number: usize) -> Result<(AnimeBase, Vec<base::ParseError>), io::Error> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "backups are not supported by this storage"))
+    }
+}
+
+pub struct TextStorage {
+    filename: String,
+    backups: usize,
+    hash: Option<u64>,
+}
+
+// items are kept in `items` for ordering and `fields` with one row per item field
+pub struct SqliteStorage {
+    filename: String,
+    connection: Connection,
+    version: i64,
+    // format version stored in the database, an older one is rewritten on the next change
+    format: u32,
+}
+
+pub fn open(kind: &str, filename: &str, backups: usize) -> Result<Box<dyn Storage>, io::Error> {
+    match kind {
+        "text" => Ok(Box::new(TextStorage { filename: filename.to_owned(), backups, hash: None })),
+        "sqlite" => Ok(Box::new(SqliteStorage::open(filename)?)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown storage `{}`", kind))),
+    }
+}
+
+fn lock_file(filename: &str) -> Result<File, io::Error> {
+    let file = File::options().create(true).truncate(false).write(true).open(format!("{}.lock", filename))?;
+    file.lock()?;
+    Ok(file)
+}
+
+// a list of a newer format would lose the data this version does not know about
+fn check_version(base: &AnimeBase) -> Result<(), io::Error> {
+    if base.version > base::FORMAT_VERSION {
+        let message = format!("list format {} is newer than supported {}", base.version, base::FORMAT_VERSION);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    Ok(())
+}
+
+fn hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn backup_name(filename: &str, number: usize) -> String {
+    format!("{}.bak.{}", filename, number)
+}
+
+fn sync_parent(filename: &Path) {
+    let parent = match filename.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    // not supported on every platform, the rename itself is already done
+    if let Ok(dir) = File::open(parent) {
+        let _ = dir.sync_all();
+    }
+}
+
+// shift `list.bak.1` -> `list.bak.2` -> ... and copy the current list to `list.bak.1`
+fn rotate_backups(filename: &str, backups: usize) -> Result<(), io::Error> {
+    if backups == 0 || !Path::new(filename).exists() {
+        return Ok(());
+    }
+    for number in (1..backups).rev() {
+        let from = backup_name(filename, number);
+        if Path::new(&from).exists() {
+            fs::rename(&from, backup_name(filename, number + 1))?;
+        }
+    }
+    fs::copy(filename, backup_name(filename, 1))?;
+    Ok(())
+}
+
+// write to a temporary file and rename it over the list, so the list is never half-written,
+// unchanged data is not written at all, so the backups keep the older versions
+pub fn write_atomic(filename: &str, backups: usize, data: &[u8]) -> Result<(), io::Error> {
+    if fs::read(filename).is_ok_and(|current| current == data) {
+        return Ok(());
+    }
+    // a linked list is replaced at the target of the link and keeps its permissions
+    let target = fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename));
+    let mut temp_name = target.clone().into_os_string();
+    temp_name.push(".tmp");
+    let mut temp = File::create(&temp_name)?;
+    let mut result = temp.write_all(data).and_then(|_| temp.sync_all());
+    if let (Ok(()), Ok(metadata)) = (&result, fs::metadata(&target)) {
+        result = temp.set_permissions(metadata.permissions());
+    }
+    drop(temp);
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_name);
+        return Err(err);
+    }
+    rotate_backups(filename, backups)?;
+    fs::rename(&temp_name, &target)?;
+    sync_parent(&target);
+    Ok(())
+}
+
+impl Storage for TextStorage {
+    fn load(&mut self) -> Result<(AnimeBase, Vec<base::ParseError>), io::Error> {
+        let buffer = fs::read_to_string(&self.filename)?;
+        self.hash = Some(hash(buffer.as_bytes()));
+        Ok(AnimeBase::parse(&buffer))
+    }
+
+    fn save(&mut self, base: &AnimeBase) -> Result<(), io::Error> {
+        check_version(base)?;
+        let mut buffer = Vec::new();
+        base.write_to(&mut buffer)?;
+        write_atomic(&self.filename, self.backups, &buffer)?;
+        self.hash = Some(hash(&buffer));
+        Ok(())
+    }
+
+    fn apply(&mut self, base: &AnimeBase, _changes: &[Change]) -> Result<(), io::Error> {
+        self.save(base)
+    }
+
+    fn lock(&self) -> Result<File, io::Error> {
+        lock_file(&self.filename)
+    }
+
+    fn is_modified(&mut self) -> Result<bool, io::Error> {
+        match fs::read(&self.filename) {
+            Ok(buffer) => Ok(self.hash != Some(hash(&buffer))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(self.hash.is_some()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // the current list becomes the first backup, so a restore can be rolled back too
+    fn restore(&mut self, number: usize) -> Result<(AnimeBase, Vec<base::ParseError>), io::Error> {
+        let buffer = fs::read_to_string(backup_name(&self.filename, number))?;
+        write_atomic(&self.filename, self.backups, buffer.as_bytes())?;
+        self.hash = Some(hash(buffer.as_bytes()));
+        Ok(AnimeBase::parse(&buffer))
+    }
+}
+
+// field values of an older database, as `base::MIGRATIONS` do for list lines, only scores can fail
+fn migrate_field(version: u32, field: &str, value: &str) -> Option<String> {
+    match field {
+        "rate" if version < 10 => base::scale_score(value),
+        _ => Some(value.to_owned()),
+    }
+}
+
+fn sqlite_error(err: rusqlite::Error) -> io::Error {
+    io::Error::other(err)
+}
+
+impl SqliteStorage {
+    fn open(filename: &str) -> Result<SqliteStorage, io::Error> {
+        let connection = Connection::open(filename).map_err(sqlite_error)?;
+        connection
+            .execute_batch(
+                "PRAGMA foreign_keys = ON;
+                 CREATE TABLE IF NOT EXISTS items (id INTEGER PRIMARY KEY, position INTEGER NOT NULL);
+                 CREATE TABLE IF NOT EXISTS fields (
+                     item INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+                     field TEXT NOT NULL,
+                     value TEXT NOT NULL,
+                     PRIMARY KEY (item, field)
+                 );
+                 CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value INTEGER NOT NULL);
+                 CREATE INDEX IF NOT EXISTS items_position ON items(position);
+                 CREATE INDEX IF NOT EXISTS fields_value ON fields(field, value);",
+            )
+            .map_err(sqlite_error)?;
+        Ok(SqliteStorage { filename: filename.to_owned(), connection, version: 0, format: base::FORMAT_VERSION })
+    }
+
+    fn save_next_id(transaction: &rusqlite::Transaction, base: &AnimeBase) -> rusqlite::Result<usize> {
+        transaction.execute(
+            "INSERT INTO meta (key, value) VALUES ('next_id', ?1) ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![base.next_id],
+        )
+    }
+
+    // changes only when another connection commits
+    fn data_version(&self) -> Result<i64, io::Error> {
+        self.connection.query_row("PRAGMA data_version", [], |row| row.get(0)).map_err(sqlite_error)
+    }
+
+    fn insert(transaction: &rusqlite::Transaction, position: usize, item: &base::Item) -> rusqlite::Result<()> {
+        transaction
+            .execute("UPDATE items SET position = position + 1 WHERE position >= ?1", params![position as i64])?;
+        transaction.execute("INSERT INTO items (id, position) VALUES (?1, ?2)", params![item.id, position as i64])?;
+        for (field, value) in item.fields() {
+            transaction.execute(
+                "INSERT INTO fields (item, field, value) VALUES (?1, ?2, ?3)",
+                params![item.id, field, value],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn delete(transaction: &rusqlite::Transaction, position: usize) -> rusqlite::Result<()> {
+        transaction.execute("DELETE FROM items WHERE position = ?1", params![position as i64])?;
+        transaction
+            .execute("UPDATE items SET position = position - 1 WHERE position > ?1", params![position as i64])?;
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<(AnimeBase, Vec<base::ParseError>), io::Error> {
+        let mut result = AnimeBase::new();
+        let mut errors = Vec::new();
+        self.version = self.data_version()?;
+        let version: u32 =
+            self.connection.query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(sqlite_error)?;
+        if version != 0 {
+            result.version = version;
+            self.format = version;
+        }
+        let next_id: Option<u32> = self
+            .connection
+            .query_row("SELECT value FROM meta WHERE key = 'next_id'", [], |row| row.get(0))
+            .optional()
+            .map_err(sqlite_error)?;
+        result.next_id = next_id.unwrap_or(1);
+        let mut items = self.connection.prepare("SELECT id FROM items ORDER BY position").map_err(sqlite_error)?;
+        let mut fields =
+            self.connection.prepare("SELECT field, value FROM fields WHERE item = ?1").map_err(sqlite_error)?;
+        let ids: Vec<u32> = items.query_map([], |row| row.get(0)).and_then(Iterator::collect).map_err(sqlite_error)?;
+        for (position, id) in ids.into_iter().enumerate() {
+            let mut item = base::Item::empty("");
+            item.id = id;
+            let values: Vec<(String, String)> = fields
+                .query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+                .and_then(Iterator::collect)
+                .map_err(sqlite_error)?;
+            for (field, value) in values {
+                let expected = match migrate_field(result.version, &field, &value) {
+                    Some(migrated) => item.set_field(&field, &migrated).map_or(Some(base::Field::Key), |_| None),
+                    None => Some(base::Field::OldScore),
+                };
+                if let Some(expected) = expected {
+                    let token = format!("{}={}", field, value);
+                    errors.push(base::ParseError::new(&token, 0, &token, expected).at_line(position + 1));
+                }
+            }
+            result.push(item);
+        }
+        Ok((result, errors))
+    }
+
+    fn save(&mut self, base: &AnimeBase) -> Result<(), io::Error> {
+        check_version(base)?;
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+        transaction.pragma_update(None, "user_version", base::FORMAT_VERSION).map_err(sqlite_error)?;
+        transaction.execute("DELETE FROM items", []).map_err(sqlite_error)?;
+        SqliteStorage::save_next_id(&transaction, base).map_err(sqlite_error)?;
+        for (position, item) in base.list.iter().enumerate() {
+            SqliteStorage::insert(&transaction, position, item).map_err(sqlite_error)?;
+        }
+        transaction.commit().map_err(sqlite_error)?;
+        self.format = base::FORMAT_VERSION;
+        Ok(())
+    }
+
+    fn apply(&mut self, base: &AnimeBase, changes: &[Change]) -> Result<(), io::Error> {
+        check_version(base)?;
+        // migrated values are only in `base`
+        if self.format < base::FORMAT_VERSION {
+            return self.save(base);
+        }
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+        transaction.pragma_update(None, "user_version", base::FORMAT_VERSION).map_err(sqlite_error)?;
+        SqliteStorage::save_next_id(&transaction, base).map_err(sqlite_error)?;
+        for change in changes {
+            match change {
+                Change::Set { id, field, new, .. } | Change::Pass { id, field, new, .. } => transaction.execute(
+                    "INSERT INTO fields (item, field, value) VALUES (?1, ?2, ?3)
+                     ON CONFLICT (item, field) DO UPDATE SET value = excluded.value",
+                    params![id, field, new],
+                ),
+                Change::Insert { index, line, .. } => match base::Item::parse(line) {
+                    Ok(item) => SqliteStorage::insert(&transaction, *index, &item).map(|_| 1),
+                    Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string())),
+                },
+                Change::Delete { index, .. } => SqliteStorage::delete(&transaction, *index).map(|_| 1),
+            }
+            .map_err(sqlite_error)?;
+        }
+        transaction.commit().map_err(sqlite_error)
+    }
+
+    fn lock(&self) -> Result<File, io::Error> {
+        lock_file(&self.filename)
+    }
+
+    fn is_modified(&mut self) -> Result<bool, io::Error> {
+        Ok(self.data_version()? != self.version)
+    }
+}